@@ -0,0 +1,561 @@
+use crate::chess_structs::{
+    ChessBoard, ChessPieceType, ChessState, InteractionType, Move, Player, PokemonType, Winner,
+    BOARD_SIZE,
+};
+use crate::settings::Difficulty;
+use crate::type_assignment::effectiveness;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// far above any material count, so a forced win or loss always decides the search
+const WIN_SCORE: i32 = 1_000_000;
+// the search always promotes to a queen, it's the right choice in almost every position
+const PROMOTION_PIECE: &str = "Queen";
+// hints search a fixed depth so the same position always gets the same advice
+pub const HINT_DEPTH: usize = 2;
+pub const DEFAULT_HINT_COUNT: usize = 3;
+// the AI takes a draw once it's at least this far behind, about a minor piece
+const DRAW_ACCEPT_MARGIN: i32 = 300;
+
+/**
+ * How far and how long the AI may search. The search deepens one ply at a time and
+ * keeps the deepest result it finished, so running out of nodes or time
+ * only costs the ply it was working on
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchLimits {
+    pub max_depth: usize,
+    pub max_nodes: u64,
+    pub max_time: Duration,
+    // the most a move's score can be nudged either way before picking the best one
+    pub noise: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoredMove {
+    pub m: Move,
+    pub score: i32,
+}
+
+// a candidate move for the player to move, with the interaction its capture would have
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Hint {
+    #[serde(rename = "move")]
+    pub m: Move,
+    pub score: i32,
+    pub interaction_type: Option<InteractionType>,
+}
+
+impl SearchLimits {
+    // searches to the given depth however long it takes, without noise
+    pub fn fixed_depth(max_depth: usize) -> Self {
+        return SearchLimits {
+            max_depth,
+            max_nodes: u64::MAX,
+            max_time: Duration::MAX,
+            noise: 0,
+        };
+    }
+}
+
+impl Difficulty {
+    pub fn search_limits(&self) -> SearchLimits {
+        let (max_depth, max_nodes, max_time_ms, noise) = match self {
+            Difficulty::Easy => (1, 2_000, 250, 150),
+            Difficulty::Medium => (2, 20_000, 1_000, 40),
+            Difficulty::Hard => (3, 200_000, 3_000, 0),
+            Difficulty::Expert => (4, 1_000_000, 8_000, 0),
+        };
+        return SearchLimits {
+            max_depth,
+            max_nodes,
+            max_time: Duration::from_millis(max_time_ms),
+            noise,
+        };
+    }
+}
+
+// the node and time budgets of one search
+struct Search {
+    ai_player: Player,
+    limits: SearchLimits,
+    started: Instant,
+    nodes: u64,
+    // the first ply always finishes so there is always a move to play
+    budget_applies: bool,
+}
+
+impl Search {
+    fn out_of_budget(&self) -> bool {
+        return self.budget_applies
+            && (self.nodes >= self.limits.max_nodes
+                || self.started.elapsed() >= self.limits.max_time);
+    }
+}
+
+/**
+ * The computer opponent. Searches the moves of the player to move with alpha-beta minimax,
+ * playing each one out with ChessState::move_piece so the type rules hold in the search too:
+ * NotVeryEffective destroys both pieces, NoEffect bounces, and SuperEffective
+ * keeps the same player on the move for one more ply.
+ * Noise is added to the scores before picking, so easier levels make mistakes
+ */
+pub fn best_move(
+    chess_state: &ChessState,
+    limits: &SearchLimits,
+    rng: &mut impl Rng,
+) -> Option<Move> {
+    let mut best: Option<(Move, i32)> = None;
+    for scored_move in score_moves(chess_state, limits) {
+        let mut score = scored_move.score;
+        if limits.noise > 0 {
+            score += rng.gen_range(-limits.noise..=limits.noise);
+        }
+        if best.is_none() || score > best.unwrap().1 {
+            best = Some((scored_move.m, score));
+        }
+    }
+    return best.map(|(m, _)| m);
+}
+
+/**
+ * Every legal move of the player to move with its score from their point of view,
+ * best first. Each move at the root is searched with a full window so its score is exact
+ */
+pub fn score_moves(chess_state: &ChessState, limits: &SearchLimits) -> Vec<ScoredMove> {
+    let search_state = search_state(chess_state);
+    let mut search = Search {
+        ai_player: chess_state.player,
+        limits: *limits,
+        started: Instant::now(),
+        nodes: 0,
+        budget_applies: false,
+    };
+    let mut scored_moves: Vec<ScoredMove> = ordered_moves(&search_state)
+        .into_iter()
+        .map(|m| ScoredMove { m, score: 0 })
+        .collect();
+    for depth in 1..=limits.max_depth.max(1) {
+        search.budget_applies = depth > 1;
+        let mut deeper_scores = Vec::new();
+        for scored_move in scored_moves.iter() {
+            let next_state = play_move(&search_state, &scored_move.m);
+            let score = minimax(
+                &mut search,
+                &next_state,
+                depth - 1,
+                -WIN_SCORE * 2,
+                WIN_SCORE * 2,
+            );
+            match score {
+                Some(score) => deeper_scores.push(ScoredMove {
+                    m: scored_move.m,
+                    score,
+                }),
+                None => break,
+            }
+        }
+        if deeper_scores.len() < scored_moves.len() {
+            // ran out of budget part way through, keep the last ply that finished
+            break;
+        }
+        // searching the best moves first next time lets alpha-beta cut off more
+        deeper_scores.sort_by_key(|scored| std::cmp::Reverse(scored.score));
+        scored_moves = deeper_scores;
+    }
+    return scored_moves;
+}
+
+/**
+ * The best `count` moves for the player to move, scored from their point of view.
+ * Reads the state without changing it, so asking for a hint never touches the game
+ */
+pub fn hints(chess_state: &ChessState, count: usize) -> Vec<Hint> {
+    if chess_state.winner != Winner::NoneYet {
+        return vec![];
+    }
+    return score_moves(chess_state, &SearchLimits::fixed_depth(HINT_DEPTH))
+        .into_iter()
+        .take(count)
+        .map(|scored_move| Hint {
+            m: scored_move.m,
+            score: scored_move.score,
+            // quiet moves have no interaction to warn about
+            interaction_type: scored_move.m.capture.and(scored_move.m.type_interaction),
+        })
+        .collect();
+}
+
+// the promotion piece the AI picks when one of its moves promotes a pawn
+pub fn promotion_piece() -> String {
+    return PROMOTION_PIECE.to_string();
+}
+
+/**
+ * The AI's pick while drafting: its most valuable piece without a type gets the type
+ * that does best against the types the other side has picked so far
+ */
+pub fn draft_pick(chess_state: &ChessState) -> Option<(usize, usize, PokemonType)> {
+    let draft = chess_state.draft.as_ref()?;
+    let player = chess_state.player;
+    let mut undrafted = Vec::new();
+    let mut opponents = Vec::new();
+    for row in 0..BOARD_SIZE {
+        for col in 0..BOARD_SIZE {
+            let piece = chess_state.chessboard.get_piece(row, col);
+            if piece.piece_type == ChessPieceType::Empty {
+                continue;
+            }
+            if piece.piece_type.get_piece_player() != player {
+                if piece.pokemon_type != PokemonType::NoType {
+                    opponents.push(piece);
+                }
+            } else if piece.pokemon_type == PokemonType::NoType {
+                undrafted.push((row, col, piece));
+            }
+        }
+    }
+    let (row, col, piece) = undrafted
+        .into_iter()
+        .max_by_key(|(_, _, piece)| piece_value(piece.piece_type))?;
+    let pokemon_type = draft
+        .types_left(player)
+        .iter()
+        .copied()
+        .max_by_key(|pokemon_type| {
+            opponents
+                .iter()
+                .map(|opponent| {
+                    let attacks = PokemonType::type_matchup(*pokemon_type, opponent.pokemon_type);
+                    let defends = PokemonType::type_matchup(opponent.pokemon_type, *pokemon_type);
+                    piece_value(opponent.piece_type) * effectiveness(attacks)
+                        - piece_value(piece.piece_type) * effectiveness(defends)
+                })
+                .sum::<i32>()
+        })?;
+    return Some((row, col, pokemon_type));
+}
+
+/**
+ * The search can't know how critical hits and misses will roll,
+ * so it plays every move out as the type chart says, without a clock
+ */
+fn search_state(chess_state: &ChessState) -> ChessState {
+    let mut search_state = chess_state.clone();
+    search_state.settings.critical_hits = false;
+    search_state.settings.misses = false;
+    search_state.settings.time_control = None;
+    search_state.clock = None;
+    search_state.in_search = true;
+    return search_state;
+}
+
+// None when the search ran out of budget, the score is then meaningless
+fn minimax(
+    search: &mut Search,
+    chess_state: &ChessState,
+    depth: usize,
+    mut alpha: i32,
+    mut beta: i32,
+) -> Option<i32> {
+    if search.out_of_budget() {
+        return None;
+    }
+    search.nodes += 1;
+    let ai_player = search.ai_player;
+    if chess_state.winner != Winner::NoneYet {
+        return Some(result_score(chess_state.winner, ai_player, depth));
+    }
+    // the moves are only generated once a node, they also tell mate and stalemate apart
+    let moves = ordered_moves(chess_state);
+    if moves.is_empty() {
+        let winner = if chess_state.chessboard.is_king_in_check(chess_state.player) {
+            Winner::from_player(chess_state.player.other_player())
+        } else {
+            Winner::Tie
+        };
+        return Some(result_score(winner, ai_player, depth));
+    }
+    if depth == 0 {
+        return Some(evaluate(&chess_state.chessboard, ai_player));
+    }
+
+    // after a super effective move the same player moves again, so whose turn it is
+    // decides between maximizing and minimizing, not the depth
+    let maximizing = chess_state.player == ai_player;
+    let mut best_score = if maximizing {
+        -WIN_SCORE * 2
+    } else {
+        WIN_SCORE * 2
+    };
+    for m in moves {
+        let next_state = play_move(chess_state, &m);
+        let score = minimax(search, &next_state, depth - 1, alpha, beta)?;
+        if maximizing {
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+        } else {
+            best_score = best_score.min(score);
+            beta = beta.min(score);
+        }
+        if beta <= alpha {
+            break;
+        }
+    }
+    return Some(best_score);
+}
+
+// prefer the quickest win and the slowest loss
+fn result_score(winner: Winner, ai_player: Player, depth: usize) -> i32 {
+    return match winner {
+        Winner::Tie | Winner::NoneYet => 0,
+        winner if winner == Winner::from_player(ai_player) => WIN_SCORE + depth as i32,
+        _ => -WIN_SCORE - depth as i32,
+    };
+}
+
+fn play_move(chess_state: &ChessState, m: &Move) -> ChessState {
+    let mut next_state = chess_state.clone();
+    next_state.move_piece(m.from_row, m.from_col, m.to_row, m.to_col);
+    if next_state.require_piece_selection {
+        let _ = next_state.select_pawn_promotion_piece(promotion_piece());
+    }
+    return next_state;
+}
+
+// captures first, they are the moves most likely to cut off the rest of the search
+fn ordered_moves(chess_state: &ChessState) -> Vec<Move> {
+    let mut moves = chess_state.all_legal_moves();
+    moves.sort_by_key(|m| match m.type_interaction {
+        Some(InteractionType::SuperEffective) => 0,
+        Some(InteractionType::Normal) if m.capture.is_some() => 1,
+        Some(InteractionType::NotVeryEffective) => 2,
+        _ => 3,
+    });
+    return moves;
+}
+
+/**
+ * Scores a board for the given player: material, plus the captures each side has lined up,
+ * weighted by how the types match up. A super effective capture is worth the most since
+ * it also earns another move, a not very effective one trades the attacker for the target,
+ * and a capture with no effect is worth nothing
+ */
+pub fn evaluate(chessboard: &ChessBoard, player: Player) -> i32 {
+    let mut score = 0;
+    for row in 0..BOARD_SIZE {
+        for col in 0..BOARD_SIZE {
+            let piece = chessboard.board[row][col];
+            if piece.piece_type == ChessPieceType::Empty {
+                continue;
+            }
+            let piece_player = piece.piece_type.get_piece_player();
+            let sign = if piece_player == player { 1 } else { -1 };
+            let mut piece_score = piece_value(piece.piece_type);
+            for m in chessboard.possible_moves_for_piece(row, col, piece_player) {
+                piece_score += matchup_value(&m);
+            }
+            score += sign * piece_score;
+        }
+    }
+    return score;
+}
+
+// the AI only takes a draw in a position it's losing
+pub fn accepts_draw(chess_state: &ChessState, player: Player) -> bool {
+    return evaluate(&chess_state.chessboard, player) <= -DRAW_ACCEPT_MARGIN;
+}
+
+fn matchup_value(m: &Move) -> i32 {
+    let capture = match m.capture {
+        Some(capture) => capture,
+        None => return 0,
+    };
+    let target_value = piece_value(capture.piece.piece_type);
+    let attacker_value = piece_value(m.piece_type);
+    return match m.type_interaction {
+        Some(InteractionType::SuperEffective) => target_value / 4,
+        Some(InteractionType::Normal) => target_value / 8,
+        // the king would go down with the piece it takes
+        Some(InteractionType::NotVeryEffective) if m.piece_type.is_king() => 0,
+        Some(InteractionType::NotVeryEffective) => (target_value - attacker_value) / 8,
+        _ => 0,
+    };
+}
+
+// kings have no material value, losing one is scored as a lost game
+pub fn piece_value(piece_type: ChessPieceType) -> i32 {
+    return match piece_type {
+        ChessPieceType::WhitePawn | ChessPieceType::BlackPawn => 100,
+        ChessPieceType::WhiteKnight | ChessPieceType::BlackKnight => 320,
+        ChessPieceType::WhiteBishop | ChessPieceType::BlackBishop => 330,
+        ChessPieceType::WhiteRook | ChessPieceType::BlackRook => 500,
+        ChessPieceType::WhiteQueen | ChessPieceType::BlackQueen => 900,
+        ChessPieceType::WhiteKing | ChessPieceType::BlackKing | ChessPieceType::Empty => 0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_state_history::ChessStateHistory;
+    use crate::chess_structs::{Piece, PokemonType};
+    use crate::game::Game;
+    use crate::settings::Settings;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn piece(piece_type: ChessPieceType, pokemon_type: PokemonType) -> Piece {
+        return Piece {
+            piece_type,
+            pokemon_type,
+        };
+    }
+
+    // kings on their home squares, everything else is up to the test
+    fn state_with_kings(player: Player) -> ChessState {
+        let mut chessboard = ChessBoard::new_normal_type_only();
+        chessboard.board = [[Piece::empty(); BOARD_SIZE]; BOARD_SIZE];
+        chessboard.board[0][4] = piece(ChessPieceType::WhiteKing, PokemonType::Normal);
+        chessboard.board[7][4] = piece(ChessPieceType::BlackKing, PokemonType::Normal);
+        chessboard.history.has_white_king_moved = true;
+        chessboard.history.has_black_king_moved = true;
+        let mut chess_state = ChessState::new_with_board(chessboard, Settings::default());
+        chess_state.player = player;
+        return chess_state;
+    }
+
+    #[test]
+    fn test_takes_a_hanging_queen() {
+        let mut chess_state = state_with_kings(Player::White);
+        chess_state.chessboard.board[0][0] = piece(ChessPieceType::WhiteRook, PokemonType::Normal);
+        chess_state.chessboard.board[5][0] = piece(ChessPieceType::BlackQueen, PokemonType::Normal);
+        let m = best_move(
+            &chess_state,
+            &SearchLimits::fixed_depth(1),
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        assert_eq!((m.from_row, m.from_col, m.to_row, m.to_col), (0, 0, 5, 0));
+    }
+
+    #[test]
+    fn test_avoids_not_very_effective_trade() {
+        assert_eq!(
+            PokemonType::type_matchup(PokemonType::Fire, PokemonType::Water),
+            InteractionType::NotVeryEffective
+        );
+        // taking the rook would cost the queen as well
+        let mut chess_state = state_with_kings(Player::White);
+        chess_state.chessboard.board[3][3] = piece(ChessPieceType::WhiteQueen, PokemonType::Fire);
+        chess_state.chessboard.board[3][7] = piece(ChessPieceType::BlackRook, PokemonType::Water);
+        let m = best_move(
+            &chess_state,
+            &SearchLimits::fixed_depth(2),
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        assert_eq!((m.from_row, m.from_col), (3, 3));
+        assert_ne!((m.to_row, m.to_col), (3, 7));
+    }
+
+    #[test]
+    fn test_finds_back_rank_mate() {
+        let mut chess_state = state_with_kings(Player::White);
+        chess_state.chessboard.board[7][4] = Piece::empty();
+        chess_state.chessboard.board[7][6] = piece(ChessPieceType::BlackKing, PokemonType::Normal);
+        for col in 5..8 {
+            chess_state.chessboard.board[6][col] =
+                piece(ChessPieceType::BlackPawn, PokemonType::Normal);
+        }
+        chess_state.chessboard.board[0][0] = piece(ChessPieceType::WhiteRook, PokemonType::Normal);
+        let m = best_move(
+            &chess_state,
+            &SearchLimits::fixed_depth(2),
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        assert_eq!((m.to_row, m.to_col), (7, 0));
+    }
+
+    #[test]
+    fn test_search_tells_mate_from_stalemate() {
+        let chess_state =
+            ChessState::from_fen("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1", Settings::default()).unwrap();
+        let scored_moves = score_moves(&chess_state, &SearchLimits::fixed_depth(1));
+        let queen_move_score = |to_row, to_col| {
+            let scored = scored_moves.iter().find(|scored| {
+                let m = scored.m;
+                return (m.from_row, m.from_col, m.to_row, m.to_col) == (0, 2, to_row, to_col);
+            });
+            return scored.unwrap().score;
+        };
+        // Qc8 mates, Qc7 leaves black without a move but not in check
+        assert!(queen_move_score(7, 2) >= WIN_SCORE);
+        assert_eq!(queen_move_score(6, 2), 0);
+        let best = scored_moves[0].m;
+        assert_eq!((best.from_row, best.to_row, best.to_col), (0, 7, 2));
+    }
+
+    #[test]
+    fn test_ai_replies_to_human_move() {
+        let settings = Settings {
+            ai_player: Some(Player::Black),
+            ..Settings::default()
+        };
+        let chess_state = ChessState::new_with_settings(settings);
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        let mut game = Game::new("ai".to_string(), chess_state_history);
+        // not the AI's turn yet
+        assert!(!game.play_ai_turns());
+        assert!(game.move_piece(1, 4, 3, 4));
+        assert!(game.play_ai_turns());
+        assert_eq!(game.get_current_state().unwrap().player, Player::White);
+    }
+
+    #[test]
+    fn test_first_ply_finishes_without_budget() {
+        let mut chess_state = state_with_kings(Player::White);
+        chess_state.chessboard.board[0][0] = piece(ChessPieceType::WhiteRook, PokemonType::Normal);
+        chess_state.chessboard.board[5][0] = piece(ChessPieceType::BlackQueen, PokemonType::Normal);
+        let limits = SearchLimits {
+            max_nodes: 1,
+            ..SearchLimits::fixed_depth(4)
+        };
+        let scored_moves = score_moves(&chess_state, &limits);
+        assert_eq!(scored_moves.len(), chess_state.all_legal_moves().len());
+        let m = scored_moves[0].m;
+        assert_eq!((m.to_row, m.to_col), (5, 0));
+    }
+
+    #[test]
+    fn test_noise_never_gives_away_a_queen() {
+        let mut chess_state = state_with_kings(Player::White);
+        chess_state.chessboard.board[0][0] = piece(ChessPieceType::WhiteRook, PokemonType::Normal);
+        chess_state.chessboard.board[5][0] = piece(ChessPieceType::BlackQueen, PokemonType::Normal);
+        let limits = Difficulty::Easy.search_limits();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let m = best_move(&chess_state, &limits, &mut rng).unwrap();
+            assert_eq!((m.to_row, m.to_col), (5, 0));
+        }
+    }
+
+    #[test]
+    fn test_hints_flag_resisted_captures() {
+        let mut chess_state = state_with_kings(Player::White);
+        chess_state.chessboard.board[3][3] = piece(ChessPieceType::WhiteQueen, PokemonType::Fire);
+        chess_state.chessboard.board[3][7] = piece(ChessPieceType::BlackRook, PokemonType::Water);
+        chess_state.chessboard.board[5][3] = piece(ChessPieceType::BlackKnight, PokemonType::Grass);
+        let before = chess_state.clone();
+        let hints = hints(&chess_state, 3);
+        assert_eq!(chess_state, before);
+        assert_eq!(hints.len(), 3);
+        assert!(hints.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        // fire burns through grass, the knight is the capture to make
+        let best = hints[0];
+        assert_eq!((best.m.to_row, best.m.to_col), (5, 3));
+        assert_eq!(best.interaction_type, Some(InteractionType::SuperEffective));
+    }
+}
@@ -1,5 +1,10 @@
-use std::collections::{HashMap, HashSet};
-use tokio::sync::{broadcast, Mutex};
+use crate::chess_structs::Player;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex, Notify};
 
 
 // Global Map available to all requests, to keep track of users
@@ -7,15 +12,48 @@ pub struct AppState {
     pub rooms: HashMap<String, RoomState>,
 }
 
-struct RoomState {
-    pub  users: HashSet<String>,
+pub struct RoomState {
+    // reconnect token -> seat, so a client that drops can take its seat back
+    pub  users: HashMap<String, Seat>,
+    // spectators currently connected, tokens in users outlive their connection
+    pub spectators: usize,
+    // every connection currently in the room, seated or spectating
+    pub connections: usize,
     // tx means transmitter, we send messages to everyone in the room
     // because each user in the room has the other end of the tx ( a rx )
     // created via tx.subscribe()
     // they are all waiting for messages to come out of their rx to send to their client
-    // and when their client sends a message, we write it into the tx so all other clients 
+    // and when their client sends a message, we write it into the tx so all other clients
     // in the room can receive it
     pub tx: broadcast::Sender<String>,
+    // wakes the room's clock timer when a move changes whose clock is running
+    pub clock_changed: Arc<Notify>,
+    pub clock_timer_started: bool,
+    // held while a change is loaded, made and saved, so two changes can't overwrite each other
+    pub game_lock: Arc<Mutex<()>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Seat {
+    White,
+    Black,
+    Spectator,
+}
+
+impl Seat {
+    /**
+     * In local play both sides are played from the same device,
+     * so any seated connection may move for either player
+     */
+    pub fn can_play_for(&self, player: Player, local_play: bool) -> bool {
+        match (self, player) {
+            (Seat::Spectator, _) => false,
+            (_, _) if local_play => true,
+            (Seat::White, Player::White) => true,
+            (Seat::Black, Player::Black) => true,
+            _ => false,
+        }
+    }
 }
 
 impl AppState {
@@ -39,14 +77,240 @@ impl AppState {
         self.add_room(name.to_string());
         return self.get_room_tx(name);
     }
+
+    /**
+     * Seat a connection in the room, returning its seat and the token it can reconnect with.
+     * A known reconnect token gets its old seat back, otherwise the connection
+     * takes the first free colour, or spectates once both are taken
+     */
+    pub fn join_room(
+        &mut self,
+        name: &str,
+        reconnect_token: Option<String>,
+        spectate: bool,
+    ) -> (Seat, String) {
+        if !self.rooms.contains_key(name) {
+            self.add_room(name.to_string());
+        }
+        let room = self.rooms.get_mut(name).unwrap();
+        if let Some(token) = reconnect_token {
+            if let Some(seat) = room.users.get(&token).copied() {
+                room.count_connection(seat);
+                return (seat, token);
+            }
+        }
+        let seat = if spectate {
+            Seat::Spectator
+        } else {
+            room.free_seat()
+        };
+        let token = RoomState::generate_reconnect_token();
+        room.users.insert(token.clone(), seat);
+        room.count_connection(seat);
+        return (seat, token);
+    }
+
+    /**
+     * Keeps a colour out of free_seat, for the AI's side of the board.
+     * The reserving token is never handed out so no client can take the seat
+     */
+    pub fn reserve_seat(&mut self, name: &str, seat: Seat) {
+        if !self.rooms.contains_key(name) {
+            self.add_room(name.to_string());
+        }
+        let room = self.rooms.get_mut(name).unwrap();
+        if !room.users.values().any(|taken| *taken == seat) {
+            room.users.insert(RoomState::generate_reconnect_token(), seat);
+        }
+    }
+
+    // a connection went away, its seat stays reserved for its reconnect token
+    pub fn leave_room(&mut self, name: &str, seat: Seat) {
+        if let Some(room) = self.rooms.get_mut(name) {
+            if seat == Seat::Spectator {
+                room.spectators = room.spectators.saturating_sub(1);
+            }
+            room.connections = room.connections.saturating_sub(1);
+            // the clock timer stops once the room is empty
+            room.clock_changed.notify_one();
+        }
+    }
+
+    pub fn spectator_count(&self, name: &str) -> usize {
+        return self.rooms.get(name).map_or(0, |room| room.spectators);
+    }
+
+    // each room gets a single clock timer, only the first caller gets to start it
+    pub fn start_clock_timer(&mut self, name: &str) -> Option<Arc<Notify>> {
+        let room = self.rooms.get_mut(name)?;
+        if room.clock_timer_started {
+            return None;
+        }
+        room.clock_timer_started = true;
+        return Some(room.clock_changed.clone());
+    }
+
+    /**
+     * Asked by the clock timer before it waits again. It's done once the game is over
+     * or nobody is connected, and the next connection to join starts a new one
+     */
+    pub fn clock_timer_done(&mut self, name: &str, game_over: bool) -> bool {
+        let room = match self.rooms.get_mut(name) {
+            Some(room) => room,
+            None => return true,
+        };
+        if !game_over && room.connections > 0 {
+            return false;
+        }
+        room.clock_timer_started = false;
+        return true;
+    }
+
+    // the lock every change to the room's game has to hold
+    pub fn game_lock(&mut self, name: &str) -> Arc<Mutex<()>> {
+        if !self.rooms.contains_key(name) {
+            self.add_room(name.to_string());
+        }
+        return self.rooms[name].game_lock.clone();
+    }
+
+    pub fn notify_clock_changed(&self, name: &str) {
+        if let Some(room) = self.rooms.get(name) {
+            room.clock_changed.notify_one();
+        }
+    }
 }
 
 impl RoomState {
     fn new() -> Self {
         Self {
-            users: HashSet::new(),
+            users: HashMap::new(),
+            spectators: 0,
+            connections: 0,
             tx: broadcast::channel(69).0,
+            clock_changed: Arc::new(Notify::new()),
+            clock_timer_started: false,
+            game_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn count_connection(&mut self, seat: Seat) {
+        if seat == Seat::Spectator {
+            self.spectators += 1;
+        }
+        self.connections += 1;
+    }
+
+    fn free_seat(&self) -> Seat {
+        for seat in [Seat::White, Seat::Black] {
+            if !self.users.values().any(|taken| *taken == seat) {
+                return seat;
+            }
         }
+        return Seat::Spectator;
+    }
+
+    fn generate_reconnect_token() -> String {
+        return rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_room_assigns_seats_in_order() {
+        let mut app_state = AppState::new();
+        let (white, _) = app_state.join_room("game", None, false);
+        let (black, _) = app_state.join_room("game", None, false);
+        let (spectator, _) = app_state.join_room("game", None, false);
+        assert_eq!(white, Seat::White);
+        assert_eq!(black, Seat::Black);
+        assert_eq!(spectator, Seat::Spectator);
+    }
+
+    #[test]
+    fn test_reconnect_token_takes_seat_back() {
+        let mut app_state = AppState::new();
+        let (_, white_token) = app_state.join_room("game", None, false);
+        app_state.join_room("game", None, false);
+        let (seat, token) = app_state.join_room("game", Some(white_token.clone()), false);
+        assert_eq!(seat, Seat::White);
+        assert_eq!(token, white_token);
+        // an unknown token is just a new connection
+        let (seat, _) = app_state.join_room("game", Some("unknown".to_string()), false);
+        assert_eq!(seat, Seat::Spectator);
+    }
+
+    #[test]
+    fn test_spectators_are_counted_while_connected() {
+        let mut app_state = AppState::new();
+        let (seat, _) = app_state.join_room("game", None, true);
+        // asking to spectate doesn't take the free white seat
+        assert_eq!(seat, Seat::Spectator);
+        let (seat, _) = app_state.join_room("game", None, false);
+        assert_eq!(seat, Seat::White);
+        let (_, token) = app_state.join_room("game", None, true);
+        assert_eq!(app_state.spectator_count("game"), 2);
+        app_state.leave_room("game", Seat::Spectator);
+        assert_eq!(app_state.spectator_count("game"), 1);
+        app_state.join_room("game", Some(token), false);
+        assert_eq!(app_state.spectator_count("game"), 2);
+    }
+
+    #[test]
+    fn test_clock_timer_starts_once_per_room() {
+        let mut app_state = AppState::new();
+        assert!(app_state.start_clock_timer("game").is_none());
+        app_state.join_room("game", None, false);
+        assert!(app_state.start_clock_timer("game").is_some());
+        assert!(app_state.start_clock_timer("game").is_none());
     }
 
+    #[test]
+    fn test_clock_timer_is_done_when_the_room_empties_or_the_game_ends() {
+        let mut app_state = AppState::new();
+        let (seat, _) = app_state.join_room("game", None, false);
+        app_state.start_clock_timer("game").unwrap();
+        assert!(!app_state.clock_timer_done("game", false));
+        app_state.leave_room("game", seat);
+        assert!(app_state.clock_timer_done("game", false));
+        // the next join can start it again
+        app_state.join_room("game", None, false);
+        assert!(app_state.start_clock_timer("game").is_some());
+        assert!(app_state.clock_timer_done("game", true));
+    }
+
+    #[test]
+    fn test_game_lock_is_shared_by_the_room() {
+        let mut app_state = AppState::new();
+        let lock = app_state.game_lock("game");
+        let _guard = lock.try_lock().unwrap();
+        assert!(app_state.game_lock("game").try_lock().is_err());
+        assert!(app_state.game_lock("other").try_lock().is_ok());
+    }
+
+    #[test]
+    fn test_reserved_seat_is_skipped() {
+        let mut app_state = AppState::new();
+        app_state.reserve_seat("game", Seat::White);
+        app_state.reserve_seat("game", Seat::White);
+        let (seat, _) = app_state.join_room("game", None, false);
+        assert_eq!(seat, Seat::Black);
+        let (seat, _) = app_state.join_room("game", None, false);
+        assert_eq!(seat, Seat::Spectator);
+    }
+
+    #[test]
+    fn test_can_play_for() {
+        assert!(Seat::White.can_play_for(Player::White, false));
+        assert!(!Seat::White.can_play_for(Player::Black, false));
+        assert!(Seat::White.can_play_for(Player::Black, true));
+        assert!(!Seat::Spectator.can_play_for(Player::White, true));
+    }
 }
@@ -1,20 +1,31 @@
 // Rust chess class that holds a board
 // and the logic for playing the game
 
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
 
 pub const BOARD_SIZE: usize = 8;
 
 use crate::chess_structs::{
-    Capture, ChessBoard, ChessHistory, ChessPieceType, InteractionType, Move, Piece, Player,
-    PokemonType, Winner,
+    Capture, ChessBoard, ChessHistory, ChessPieceType, InteractionType, Move, MoveRolls, Piece,
+    Player, PokemonType, Winner,
 };
+use crate::settings::{Settings, TypeAssignment};
 
 impl ChessBoard {
     pub fn new() -> Self {
-        let board = ChessBoard::initialize_board();
-        return board;
+        return ChessBoard::new_with_seed(thread_rng().gen());
+    }
+    // the same seed always shuffles the types into the same places
+    pub fn new_with_seed(seed: u64) -> Self {
+        return ChessBoard::new_with_type_assignment(TypeAssignment::Random, seed);
+    }
+    pub fn new_with_type_assignment(type_assignment: TypeAssignment, seed: u64) -> Self {
+        let (white_types, black_types) = type_assignment.deal(&mut StdRng::seed_from_u64(seed));
+        return ChessBoard {
+            board: Self::create_board_with_types(white_types, black_types),
+            history: ChessHistory::new(),
+        };
     }
     pub fn new_normal_type_only() -> Self {
         let board = ChessBoard::initialize_board_all_normal();
@@ -34,16 +45,21 @@ impl ChessBoard {
     pub fn last_move_interaction_type(&self) -> Option<InteractionType> {
         let last_move = self.history.last_move();
         if let Some(last_move) = last_move {
+            // a missed attack never interacted with the defending piece
+            if last_move.missed {
+                return None;
+            }
             return last_move.type_interaction;
         }
         return None;
     }
 
+    /**
+     * Only decides games where a king is gone,
+     * checkmate and stalemate are decided by ChessState::get_winner
+     */
     pub fn get_winner(&self, current_player: Player) -> Winner {
-        let opponent = match current_player {
-            Player::White => Player::Black,
-            Player::Black => Player::White,
-        };
+        let opponent = current_player.other_player();
 
         let current_king_position = self.find_king_position(current_player);
         let opponent_king_position = self.find_king_position(opponent);
@@ -52,28 +68,90 @@ impl ChessBoard {
             (None, None) => Winner::Tie,
             (Some(_), None) => Winner::from_player(current_player),
             (None, Some(_)) => Winner::from_player(opponent),
-            (Some(current_king_pos), Some(_)) => {
-                let current_king_in_check = self.location_under_attack(
-                    current_king_pos.0,
-                    current_king_pos.1,
-                    current_player,
+            (Some(_), Some(_)) => Winner::NoneYet,
+        }
+    }
+
+    /**
+     * Identifies a position for threefold repetition: every piece with its pokemon type,
+     * the player to move, castling rights, en passant and a pending super effective extra move
+     */
+    pub fn position_key(&self, player: Player) -> String {
+        let mut key = String::new();
+        for row in 0..BOARD_SIZE {
+            for col in 0..BOARD_SIZE {
+                let piece = self.get_piece(row, col);
+                key.push(piece.piece_type.fen_char());
+                key.push((b'a' + piece.pokemon_type as u8) as char);
+            }
+        }
+        key += &format!(
+            "{:?}|{}{}{}{}|{:?}|{:?}",
+            player,
+            self.history.can_castle_kingside(true),
+            self.history.can_castle_queenside(true),
+            self.history.can_castle_kingside(false),
+            self.history.can_castle_queenside(false),
+            self.history.last_move_enables_en_passant(),
+            self.history.last_move_super_effective(),
+        );
+        return key;
+    }
+
+    /**
+     * Kings can't capture each other and a lone minor piece can't checkmate,
+     * so these positions can't be won unless a king destroys itself on a
+     * not very effective capture
+     */
+    pub fn has_insufficient_material(&self) -> bool {
+        let mut pieces = Vec::new();
+        for row in 0..BOARD_SIZE {
+            for col in 0..BOARD_SIZE {
+                let piece = self.get_piece(row, col);
+                if piece.piece_type != ChessPieceType::Empty && !piece.piece_type.is_king() {
+                    pieces.push((row, col, piece));
+                }
+            }
+        }
+        let material_is_insufficient = match pieces.as_slice() {
+            [] => true,
+            [(_, _, piece)] => matches!(
+                piece.piece_type,
+                ChessPieceType::WhiteKnight
+                    | ChessPieceType::BlackKnight
+                    | ChessPieceType::WhiteBishop
+                    | ChessPieceType::BlackBishop
+            ),
+            [(row_1, col_1, piece_1), (row_2, col_2, piece_2)] => {
+                // one bishop each, both on the same colour squares
+                let bishops = matches!(
+                    (piece_1.piece_type, piece_2.piece_type),
+                    (ChessPieceType::WhiteBishop, ChessPieceType::BlackBishop)
+                        | (ChessPieceType::BlackBishop, ChessPieceType::WhiteBishop)
                 );
-                let current_king_has_moves = !self
-                    .possible_moves_for_piece_unfiltered(
-                        current_king_pos.0,
-                        current_king_pos.1,
-                        current_player,
-                        true,
-                    )
-                    .is_empty();
-
-                if current_king_in_check && !current_king_has_moves {
-                    Winner::from_player(opponent)
-                } else {
-                    Winner::NoneYet
+                bishops && (row_1 + col_1) % 2 == (row_2 + col_2) % 2
+            }
+            _ => false,
+        };
+        if !material_is_insufficient {
+            return false;
+        }
+        // a king taking a piece it's not very effective against destroys itself and loses
+        for player in [Player::White, Player::Black] {
+            if let Some(king_position) = self.find_king_position(player) {
+                let king = self.get_piece(king_position.0, king_position.1);
+                for (_, _, piece) in &pieces {
+                    let interaction =
+                        PokemonType::type_matchup(king.pokemon_type, piece.pokemon_type);
+                    if ChessBoard::piece_same_as_player(*piece, &player.other_player())
+                        && interaction == InteractionType::NotVeryEffective
+                    {
+                        return false;
+                    }
                 }
             }
         }
+        return true;
     }
 
     fn possible_moves_for_piece_unfiltered(
@@ -109,8 +187,10 @@ impl ChessBoard {
             if self.get_piece(row, new_col).piece_type != ChessPieceType::Empty {
                 return false;
             }
-            // alternatlively make sure this piece isn't under attack
-            if self.location_under_attack(row, new_col, player) {
+        }
+        // the king can't castle out of, through or into check
+        for king_col in 4..7 {
+            if self.location_under_attack(row, king_col, player) {
                 return false;
             }
         }
@@ -126,8 +206,10 @@ impl ChessBoard {
             if self.get_piece(row, new_col).piece_type != ChessPieceType::Empty {
                 return false;
             }
-            // alternatlively make sure this piece isn't under attack
-            if self.location_under_attack(row, new_col, player) {
+        }
+        // only the rook passes over the b file, so it may be attacked
+        for king_col in 2..5 {
+            if self.location_under_attack(row, king_col, player) {
                 return false;
             }
         }
@@ -159,7 +241,12 @@ impl ChessBoard {
     pub fn possible_moves_for_piece(&self, row: usize, col: usize, player: Player) -> Vec<Move> {
         let mut moves = self.possible_moves_for_piece_unfiltered(row, col, player, false);
         if let Some(position) = self.history.last_move_super_effective() {
-            moves = ChessBoard::filter_moves_if_super_effective(moves, position);
+            // the extra move only belongs to the player who made the super effective move,
+            // if their piece had no moves the turn has already passed to the other player
+            let super_effective_piece = self.get_piece(position.0, position.1);
+            if ChessBoard::piece_same_as_player(super_effective_piece, &player) {
+                moves = ChessBoard::filter_moves_if_super_effective(moves, position);
+            }
         }
 
         return moves;
@@ -188,11 +275,33 @@ impl ChessBoard {
         to_row: usize,
         to_col: usize,
         player: Player,
+    ) -> ChessBoard {
+        return self.move_piece_with_settings(
+            from_row,
+            from_col,
+            to_row,
+            to_col,
+            player,
+            &Settings::default(),
+            MoveRolls::none(),
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn move_piece_with_settings(
+        &self,
+        from_row: usize,
+        from_col: usize,
+        to_row: usize,
+        to_col: usize,
+        player: Player,
+        settings: &Settings,
+        rolls: MoveRolls,
     ) -> ChessBoard {
         if self.is_move_valid(from_row, from_col, to_row, to_col, player) {
             return self
                 .clone()
-                .execute_move(from_row, from_col, to_row, to_col);
+                .execute_move(from_row, from_col, to_row, to_col, settings, rolls);
         }
         return self.clone();
     }
@@ -238,13 +347,35 @@ impl ChessBoard {
         from_col: usize,
         to_row: usize,
         to_col: usize,
+        settings: &Settings,
+        rolls: MoveRolls,
     ) -> ChessBoard {
         let mut new_board = self.clone();
         let piece = self.get_piece(from_row, from_col);
-        let move_to_execute = self.find_move(from_row, from_col, to_row, to_col).unwrap();
+        let mut move_to_execute = self.find_move(from_row, from_col, to_row, to_col).unwrap();
+
+        // A critical hit breaks through a resisted attack, the capture goes through as normal
+        let resisted = matches!(
+            move_to_execute.type_interaction,
+            Some(InteractionType::NotVeryEffective) | Some(InteractionType::NoEffect)
+        );
+        if settings.misses && rolls.miss && move_to_execute.capture.is_some() {
+            // The attack misses, nothing on the board changes but the move is still recorded
+            move_to_execute.missed = true;
+            new_board.history.add_move(move_to_execute);
+            return new_board;
+        }
+        if settings.critical_hits && rolls.critical_hit && resisted {
+            move_to_execute.critical_hit = true;
+        }
+        let type_interaction = if move_to_execute.critical_hit {
+            Some(InteractionType::Normal)
+        } else {
+            move_to_execute.type_interaction
+        };
 
         // Check the interaction type and handle "Not Very Effective" outcome
-        if let Some(type_interaction) = move_to_execute.type_interaction {
+        if let Some(type_interaction) = type_interaction {
             match type_interaction {
                 InteractionType::NotVeryEffective => {
                     // Destroy both pieces if the interaction is "Not Very Effective"
@@ -296,43 +427,7 @@ impl ChessBoard {
         };
     }
 
-    fn initialize_board() -> Self {
-        let mut rng = thread_rng();
-
-        // Initialize pieces to a random pokemon type but don't repeat
-        let mut white_types = vec![
-            PokemonType::Normal,
-            PokemonType::Fire,
-            PokemonType::Water,
-            PokemonType::Electric,
-            PokemonType::Grass,
-            PokemonType::Ice,
-            PokemonType::Fighting,
-            PokemonType::Poison,
-            PokemonType::Ground,
-            PokemonType::Flying,
-            PokemonType::Psychic,
-            PokemonType::Bug,
-            PokemonType::Rock,
-            PokemonType::Ghost,
-            PokemonType::Dragon,
-            PokemonType::Dark,
-            PokemonType::Steel,
-            PokemonType::Fairy,
-        ];
-        let mut black_types = white_types.clone();
-
-        white_types.shuffle(&mut rng);
-        black_types.shuffle(&mut rng);
-        let board = Self::create_board_with_types(white_types.clone(), black_types.clone());
-
-        ChessBoard {
-            board,
-            history: ChessHistory::new(),
-        }
-    }
-
-    fn create_board_with_types(
+    pub fn create_board_with_types(
         mut white_types: Vec<PokemonType>,
         mut black_types: Vec<PokemonType>,
     ) -> [[Piece; BOARD_SIZE]; BOARD_SIZE] {
@@ -591,6 +686,32 @@ mod tests {
         assert!(board.get_piece(0, 4).piece_type == ChessPieceType::WhiteKing);
     }
 
+    #[test]
+    fn test_cant_castle_out_of_check() {
+        let mut board = ChessBoard::new_normal_type_only();
+        board.board[0][5] = Piece::empty();
+        board.board[0][6] = Piece::empty();
+        // a black queen down the open e file checks the king, f1 and g1 are safe
+        board.board[1][4] = Piece::empty();
+        board.board[4][4] = board.board[7][3];
+        board = board.move_piece(0, 4, 0, 6, Player::White);
+        assert!(board.get_piece(0, 4).piece_type == ChessPieceType::WhiteKing);
+    }
+
+    #[test]
+    fn test_castle_queenside_with_b_file_attacked() {
+        // the king never crosses the b file, only the rook does
+        let mut board = ChessBoard::new_normal_type_only();
+        board.board[0][1] = Piece::empty();
+        board.board[0][2] = Piece::empty();
+        board.board[0][3] = Piece::empty();
+        board.board[1][1] = Piece::empty();
+        board.board[4][1] = board.board[7][0];
+        board = board.move_piece(0, 4, 0, 2, Player::White);
+        assert!(board.get_piece(0, 2).piece_type == ChessPieceType::WhiteKing);
+        assert!(board.get_piece(0, 3).piece_type == ChessPieceType::WhiteRook);
+    }
+
     #[test]
     fn test_castling_would_be_in_check_during_swap() {
         // You can't castle if, for example, a bishop has line of sight on a square in between your
@@ -655,4 +776,54 @@ mod tests {
             ChessPieceType::BlackPawn
         ));
     }
+
+    fn board_with_resisted_pawn_capture() -> ChessBoard {
+        // white pawn on e4 can take a black pawn on d5 that resists it
+        let mut board = ChessBoard::new_normal_type_only();
+        board = board.move_piece(1, 4, 3, 4, Player::White);
+        board = board.move_piece(6, 3, 4, 3, Player::Black);
+        board.board[4][3].pokemon_type = PokemonType::Rock;
+        return board;
+    }
+
+    #[test]
+    fn test_not_very_effective_destroys_both_pieces() {
+        let mut board = board_with_resisted_pawn_capture();
+        board = board.move_piece(3, 4, 4, 3, Player::White);
+        assert!(board.get_piece(3, 4).piece_type == ChessPieceType::Empty);
+        assert!(board.get_piece(4, 3).piece_type == ChessPieceType::Empty);
+        assert!(!board.history.last_move().unwrap().critical_hit);
+    }
+
+    #[test]
+    fn test_critical_hit_lands_resisted_capture() {
+        let mut board = board_with_resisted_pawn_capture();
+        let rolls = MoveRolls {
+            critical_hit: true,
+            miss: false,
+        };
+        let settings = Settings::new(false, true, false);
+        board = board.move_piece_with_settings(3, 4, 4, 3, Player::White, &settings, rolls);
+        assert!(board.get_piece(3, 4).piece_type == ChessPieceType::Empty);
+        assert!(board.get_piece(4, 3).piece_type == ChessPieceType::WhitePawn);
+        let last_move = board.history.last_move().unwrap();
+        assert!(last_move.critical_hit);
+        assert!(last_move.type_interaction == Some(InteractionType::NotVeryEffective));
+    }
+
+    #[test]
+    fn test_missed_capture_leaves_board_unchanged() {
+        let board = board_with_resisted_pawn_capture();
+        let rolls = MoveRolls {
+            critical_hit: true,
+            miss: true,
+        };
+        let settings = Settings::new(false, true, true);
+        let new_board = board.move_piece_with_settings(3, 4, 4, 3, Player::White, &settings, rolls);
+        assert!(new_board.board == board.board);
+        let last_move = new_board.history.last_move().unwrap();
+        assert!(last_move.missed);
+        assert!(!last_move.critical_hit);
+        assert!(new_board.last_move_interaction_type().is_none());
+    }
 }
@@ -0,0 +1,98 @@
+use crate::chess_structs::{ChessClock, Player};
+use crate::settings::TimeControl;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/**
+ * Clock rules
+ * - the clocks start once white has made their first move
+ * - a turn lasts until the other player is to move, so a super effective extra move
+ *   (and picking a pawn promotion piece) is played on the same turn, with the same clock
+ *   still running and without another delay or increment
+ * - the delay is given once per turn before the clock starts counting down,
+ *   the increment is added once the turn is over
+ */
+impl ChessClock {
+    pub fn new(time_control: TimeControl) -> Self {
+        ChessClock {
+            time_control,
+            white_remaining_ms: time_control.base_ms,
+            black_remaining_ms: time_control.base_ms,
+            running: None,
+            turn_started_at_ms: 0,
+        }
+    }
+
+    pub fn now_ms() -> u64 {
+        return SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64);
+    }
+
+    pub fn remaining_ms(&self, player: Player, now_ms: u64) -> u64 {
+        let remaining_ms = match player {
+            Player::White => self.white_remaining_ms,
+            Player::Black => self.black_remaining_ms,
+        };
+        if self.running != Some(player) {
+            return remaining_ms;
+        }
+        return remaining_ms.saturating_sub(self.time_used_ms(now_ms));
+    }
+
+    // how long until the running player runs out of time, None if no clock is running
+    pub fn time_until_flag_ms(&self, now_ms: u64) -> Option<u64> {
+        return self.running.map(|player| self.remaining_ms(player, now_ms));
+    }
+
+    /**
+     * Called after every move with the player to move next. Nothing changes while
+     * the same player keeps moving, otherwise the time used is charged and the other
+     * player's clock starts
+     */
+    pub fn start_turn(&mut self, player: Player, now_ms: u64) {
+        if self.running == Some(player) {
+            return;
+        }
+        if let Some(previous_player) = self.running {
+            let remaining_ms = self.remaining_ms(previous_player, now_ms);
+            self.set_remaining_ms(
+                previous_player,
+                remaining_ms + self.time_control.increment_ms,
+            );
+        }
+        self.running = Some(player);
+        self.turn_started_at_ms = now_ms;
+    }
+
+    // charge the running player for their turn so far and stop all clocks
+    pub fn stop(&mut self, now_ms: u64) {
+        if let Some(player) = self.running {
+            let remaining_ms = self.remaining_ms(player, now_ms);
+            self.set_remaining_ms(player, remaining_ms);
+        }
+        self.running = None;
+    }
+
+    /**
+     * For an old state brought back by undo or redo. The players keep the time they have
+     * on the stopped live clock, going back doesn't give any back, and the player to move
+     * in the old state starts their turn over
+     */
+    pub fn resume_from(&mut self, live_clock: &ChessClock, now_ms: u64) {
+        self.white_remaining_ms = live_clock.white_remaining_ms;
+        self.black_remaining_ms = live_clock.black_remaining_ms;
+        self.turn_started_at_ms = now_ms;
+    }
+
+    fn time_used_ms(&self, now_ms: u64) -> u64 {
+        let elapsed_ms = now_ms.saturating_sub(self.turn_started_at_ms);
+        return elapsed_ms.saturating_sub(self.time_control.delay_ms.unwrap_or(0));
+    }
+
+    fn set_remaining_ms(&mut self, player: Player, remaining_ms: u64) {
+        match player {
+            Player::White => self.white_remaining_ms = remaining_ms,
+            Player::Black => self.black_remaining_ms = remaining_ms,
+        }
+    }
+}
@@ -19,10 +19,21 @@ impl ChessHistory {
             has_white_king_side_rook_moved: false,
             has_black_queen_side_rook_moved: false,
             has_black_king_side_rook_moved: false,
+            halfmove_clock: 0,
         };
     }
 
     pub fn add_move(&mut self, m: Move) {
+        if m.is_irreversible() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        // a missed capture leaves the piece where it was, so castling rights are untouched
+        if m.missed {
+            self.last_move = Some(m);
+            return;
+        }
         // check if the move affects castling rights
         if m.piece_type == ChessPieceType::WhiteKing {
             self.has_white_king_moved = true;
@@ -100,10 +111,11 @@ impl ChessHistory {
      */
     pub fn last_move_super_effective(&self) -> Option<(usize, usize)> {
         if let Some(last_move) = self.last_move() {
-            if last_move
-                .type_interaction
-                .unwrap_or(InteractionType::Normal)
-                == InteractionType::SuperEffective
+            if !last_move.missed
+                && last_move
+                    .type_interaction
+                    .unwrap_or(InteractionType::Normal)
+                    == InteractionType::SuperEffective
             {
                 return Some((last_move.to_row, last_move.to_col));
             }
@@ -114,6 +126,9 @@ impl ChessHistory {
 
     pub fn last_move_requires_pawn_promotion(&self) -> bool {
         if let Some(last_move) = self.last_move {
+            if last_move.missed {
+                return false;
+            }
             if last_move.piece_type == ChessPieceType::WhitePawn
                 && last_move.to_row == ChessHistory::BLACK_KING_START_ROW
             {
@@ -1,20 +1,43 @@
 use crate::chess_structs::{
-    ChessBoard, ChessState, InfoMessage, InteractionType, Move, Player, Winner,
+    ChessBoard, ChessClock, ChessState, Draft, InfoMessage, InteractionType, Move, MoveRolls,
+    Player, Winner, BOARD_SIZE,
 };
+use crate::settings::{Settings, TypeAssignment};
+use rand::Rng;
 
 
 /**
  * Main Difference between chess_state and chess_board
- * is that chess_state holds onto the player, and ui information like info_message
+ * is that chess_state holds onto the player, the rules (settings) the game is played with,
+ * and ui information like info_message
  * or if the user needs to select a piece and can't play until they do
  *
  */
 impl ChessState {
+    // fifty moves by each player
+    const FIFTY_MOVE_RULE_PLIES: usize = 100;
+
     pub fn new() -> Self {
-        let chessboard = ChessBoard::new();
+        return ChessState::new_with_settings(Settings::default());
+    }
+
+    pub fn new_with_settings(settings: Settings) -> Self {
+        let seed = settings.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let chessboard = ChessBoard::new_with_type_assignment(settings.type_assignment, seed);
+        let mut chess_state = ChessState::new_with_board(chessboard, settings);
+        chess_state.rng_seed = seed;
+        if settings.type_assignment == TypeAssignment::Draft {
+            chess_state.draft = Some(Draft::new());
+        }
+        return chess_state;
+    }
+
+    pub fn new_with_board(chessboard: ChessBoard, settings: Settings) -> Self {
         let player = Player::White;
         let winner = Winner::NoneYet;
         let info_message = None;
+        let position_history = vec![chessboard.position_key(player)];
+        let clock = settings.time_control.map(ChessClock::new);
         let chess_state = ChessState {
             chessboard,
             player,
@@ -22,6 +45,15 @@ impl ChessState {
             info_message,
             require_piece_selection: false,
             turn_count: 0,
+            rng_seed: settings
+                .seed
+                .unwrap_or_else(|| rand::thread_rng().gen()),
+            settings,
+            position_history,
+            draw_offer: None,
+            clock,
+            draft: None,
+            in_search: false,
         };
         return chess_state;
     }
@@ -40,28 +72,47 @@ impl ChessState {
         from_col: usize,
         to_row: usize,
         to_col: usize,
+    ) -> bool {
+        let rolls = MoveRolls::roll(self.rng_seed, self.turn_count);
+        return self.move_piece_with_rolls(from_row, from_col, to_row, to_col, rolls);
+    }
+
+    // move_piece with rolls that were already made, e.g. the ones kept in a save
+    pub fn move_piece_with_rolls(
+        &mut self,
+        from_row: usize,
+        from_col: usize,
+        to_row: usize,
+        to_col: usize,
+        rolls: MoveRolls,
     ) -> bool {
         if self.require_piece_selection {
             return false;
         }
-        if !self
-            .chessboard
-            .is_move_valid(from_row, from_col, to_row, to_col, self.player)
-        {
+        let is_legal = self.in_search
+            || self
+                .legal_moves_for_piece(from_row, from_col)
+                .iter()
+                .any(|m| m.to_row == to_row && m.to_col == to_col);
+        if self.winner != Winner::NoneYet || !is_legal {
             return false;
         }
-        self.chessboard =
-            self.chessboard
-                .move_piece(from_row, from_col, to_row, to_col, self.player.clone());
+        self.chessboard = self.chessboard.move_piece_with_settings(
+            from_row,
+            from_col,
+            to_row,
+            to_col,
+            self.player.clone(),
+            &self.settings,
+            rolls,
+        );
         let interaction_type = self.chessboard.last_move_interaction_type();
         let mut moves_available = true;
         let is_super_effective = interaction_type == Some(InteractionType::SuperEffective);
         let pawn_promotion = self.chessboard.history.last_move_requires_pawn_promotion();
         if is_super_effective {
-            // check if the piece has moves available
-            let moves =
-                self.chessboard
-                    .possible_moves_for_piece(to_row, to_col, self.player.clone());
+            // check if the piece has legal moves available
+            let moves = self.legal_moves_for_piece(to_row, to_col);
             if moves.is_empty() && !pawn_promotion {
                 // flip it over to the other player and update the info message to match
                 self.player = self.player.other_player();
@@ -72,31 +123,227 @@ impl ChessState {
                 self.player = self.player.other_player();
             }
         }
-        self.info_message = InfoMessage::get_message_from_interaction_type(
-            interaction_type.unwrap_or(InteractionType::Normal),
-            moves_available,
-        );
+        self.info_message =
+            InfoMessage::get_message_from_move(self.chessboard.history.last_move(), moves_available);
         self.require_piece_selection = pawn_promotion;
+        // moving on instead of answering turns down a draw offer
+        self.draw_offer = None;
+        let last_move = self.chessboard.history.last_move().unwrap();
+        if last_move.is_irreversible() {
+            self.position_history.clear();
+        }
+        // a promotion isn't a finished position until the piece is picked
+        if !pawn_promotion {
+            self.record_position();
+        }
         // check if the game is over
         // after new player is set
-        self.winner = self.chessboard.get_winner(self.player);
+        self.update_winner();
         self.turn_count += 1;
         return true;
     }
 
+    /**
+     * The board only knows about captured kings, checkmate and stalemate depend on
+     * every legal move of the player to move, including being limited to the one piece
+     * after a super effective move
+     */
+    pub fn get_winner(&self) -> Winner {
+        return self.get_winner_and_reason().0;
+    }
+
+    /**
+     * Also explains why a game was drawn. Checkmate wins over the draw rules,
+     * the fifty-move rule counts every ply, super effective extra moves included
+     */
+    pub fn get_winner_and_reason(&self) -> (Winner, Option<InfoMessage>) {
+        let winner = self.chessboard.get_winner(self.player);
+        // mid pawn promotion the player isn't done with their move yet
+        if winner != Winner::NoneYet || self.require_piece_selection {
+            return (winner, None);
+        }
+        if !self.in_search && self.all_legal_moves().is_empty() {
+            if self.chessboard.is_king_in_check(self.player) {
+                return (Winner::from_player(self.player.other_player()), None);
+            }
+            return (Winner::Tie, Some(InfoMessage::Stalemate));
+        }
+        if self.chessboard.has_insufficient_material() {
+            return (Winner::Tie, Some(InfoMessage::InsufficientMaterial));
+        }
+        if self.chessboard.history.halfmove_clock >= ChessState::FIFTY_MOVE_RULE_PLIES {
+            return (Winner::Tie, Some(InfoMessage::FiftyMoveRule));
+        }
+        if self.position_repetitions() >= 3 {
+            return (Winner::Tie, Some(InfoMessage::ThreefoldRepetition));
+        }
+        return (Winner::NoneYet, None);
+    }
+
+    fn update_winner(&mut self) {
+        let (winner, reason) = self.get_winner_and_reason();
+        self.winner = winner;
+        if reason.is_some() {
+            self.info_message = reason;
+        }
+    }
+
+    fn record_position(&mut self) {
+        let key = self.chessboard.position_key(self.player);
+        self.position_history.push(key);
+    }
+
+    // how many times the current position has come up
+    fn position_repetitions(&self) -> usize {
+        let key = self.chessboard.position_key(self.player);
+        return self
+            .position_history
+            .iter()
+            .filter(|position| **position == key)
+            .count();
+    }
+
+    /**
+     * The state as it's sent to the players. The seed decides every critical hit and miss,
+     * anyone who had it could see the rolls coming, so it stays on the server until the
+     * game is over
+     */
+    pub fn for_players(&self) -> ChessState {
+        let mut chess_state = self.clone();
+        if chess_state.winner == Winner::NoneYet {
+            chess_state.rng_seed = 0;
+            chess_state.settings.seed = None;
+        }
+        return chess_state;
+    }
+
+    // keep the clock in step with the player to move, and stop it once the game is over
+    pub fn update_clock(&mut self, now_ms: u64) {
+        let player = self.player;
+        let game_over = self.winner != Winner::NoneYet;
+        // the clocks wait for white's first move, however long the draft takes
+        if self.turn_count == 0 && !game_over {
+            return;
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            if game_over {
+                clock.stop(now_ms);
+            } else {
+                clock.start_turn(player, now_ms);
+            }
+        }
+    }
+
+    // returns true if the player whose clock is running just lost on time
+    pub fn flag_if_out_of_time(&mut self, now_ms: u64) -> bool {
+        if self.winner != Winner::NoneYet {
+            return false;
+        }
+        let clock = match self.clock.as_mut() {
+            Some(clock) => clock,
+            None => return false,
+        };
+        let player = match clock.running {
+            Some(player) => player,
+            None => return false,
+        };
+        if clock.remaining_ms(player, now_ms) > 0 {
+            return false;
+        }
+        clock.stop(now_ms);
+        self.winner = Winner::from_player(player.other_player());
+        self.info_message = Some(InfoMessage::OutOfTime);
+        self.draw_offer = None;
+        return true;
+    }
+
+    pub fn resign(&mut self, player: Player) -> Result<(), String> {
+        if self.winner != Winner::NoneYet {
+            return Err("The game is already over".to_string());
+        }
+        self.winner = Winner::from_player(player.other_player());
+        self.info_message = Some(InfoMessage::Resigned);
+        self.draw_offer = None;
+        return Ok(());
+    }
+
+    pub fn offer_draw(&mut self, player: Player) -> Result<(), String> {
+        if self.winner != Winner::NoneYet {
+            return Err("The game is already over".to_string());
+        }
+        if self.draw_offer.is_some() {
+            return Err("A draw has already been offered".to_string());
+        }
+        self.draw_offer = Some(player);
+        return Ok(());
+    }
+
+    // only the player the draw was offered to can accept or decline it
+    pub fn accept_draw(&mut self, player: Player) -> Result<(), String> {
+        self.answer_draw_offer(player)?;
+        self.winner = Winner::Tie;
+        self.info_message = Some(InfoMessage::DrawAgreed);
+        return Ok(());
+    }
+
+    pub fn decline_draw(&mut self, player: Player) -> Result<(), String> {
+        return self.answer_draw_offer(player);
+    }
+
+    fn answer_draw_offer(&mut self, player: Player) -> Result<(), String> {
+        if self.winner != Winner::NoneYet {
+            return Err("The game is already over".to_string());
+        }
+        match self.draw_offer {
+            Some(offered_by) if offered_by != player => {
+                self.draw_offer = None;
+                return Ok(());
+            }
+            Some(_) => return Err("You can't answer your own draw offer".to_string()),
+            None => return Err("There is no draw offer".to_string()),
+        }
+    }
+
     pub fn get_valid_moves(&self, row: usize, col: usize) -> Vec<Move> {
-        if self.chessboard.get_winner(self.player) != Winner::NoneYet {
+        if self.winner != Winner::NoneYet {
+            return vec![];
+        }
+        return self.legal_moves_for_piece(row, col);
+    }
+
+    // every move the player to move can make, across all of their pieces
+    pub fn all_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for row in 0..BOARD_SIZE {
+            for col in 0..BOARD_SIZE {
+                moves.extend(self.legal_moves_for_piece(row, col));
+            }
+        }
+        return moves;
+    }
+
+    fn legal_moves_for_piece(&self, row: usize, col: usize) -> Vec<Move> {
+        // nothing moves until every piece has its type
+        if self.is_drafting() {
             return vec![];
         }
         let moves = self
             .chessboard
             .possible_moves_for_piece(row, col, self.player);
-        let current_player = self.player.clone();
+        let current_player = self.player;
         let mut valid_moves = Vec::new();
         for m in moves {
-            let mut new_board = self.chessboard.clone();
-            new_board =
-                new_board.move_piece(m.from_row, m.from_col, m.to_row, m.to_col, self.player);
+            // play the move out under this game's rules, without any rolls, to see if it
+            // leaves the king in check
+            let new_board = self.chessboard.move_piece_with_settings(
+                m.from_row,
+                m.from_col,
+                m.to_row,
+                m.to_col,
+                self.player,
+                &self.settings,
+                MoveRolls::none(),
+            );
             if !new_board.is_king_in_check(current_player) {
                 valid_moves.push(m);
             }
@@ -117,6 +364,9 @@ impl ChessState {
         }
         self.player = self.other_player_considering_board();
         self.require_piece_selection = false;
+        self.record_position();
+        // the promoted piece can give check, or leave the other player without moves
+        self.update_winner();
         return Ok(());
     }
 }
@@ -125,6 +375,26 @@ impl ChessState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chess_structs::{ChessPieceType, Piece, PokemonType};
+    use crate::settings::TimeControl;
+
+    fn normal_piece(piece_type: ChessPieceType) -> Piece {
+        return Piece {
+            piece_type,
+            pokemon_type: PokemonType::Normal,
+        };
+    }
+
+    // a state with nothing on the board, kings can't castle since they won't start at home
+    fn empty_state(player: Player) -> ChessState {
+        let mut chessboard = ChessBoard::new_normal_type_only();
+        chessboard.board = [[Piece::empty(); BOARD_SIZE]; BOARD_SIZE];
+        chessboard.history.has_white_king_moved = true;
+        chessboard.history.has_black_king_moved = true;
+        let mut chess_state = ChessState::new_with_board(chessboard, Settings::default());
+        chess_state.player = player;
+        return chess_state;
+    }
 
     #[test]
     fn test_get_valid_moves() {
@@ -136,4 +406,328 @@ mod tests {
         assert!(valid_moves[1].to_row == 3);
         assert!(valid_moves[1].to_col == 0);
     }
+
+    #[test]
+    fn test_move_rolls_replay_the_same() {
+        // undoing a move and playing it again, or reloading from disk, must not reroll it
+        let mut chess_state = ChessState::new_with_settings(Settings::new(false, true, true));
+        chess_state.move_piece(1, 4, 3, 4);
+        chess_state.move_piece(6, 3, 4, 3);
+        let mut replayed_state = chess_state.clone();
+        chess_state.move_piece(3, 4, 4, 3);
+        replayed_state.move_piece(3, 4, 4, 3);
+        assert_eq!(chess_state, replayed_state);
+    }
+
+    #[test]
+    fn test_seed_deals_the_same_board() {
+        let seeded = |seed| {
+            let settings = Settings {
+                seed: Some(seed),
+                ..Settings::default()
+            };
+            return ChessState::new_with_settings(settings);
+        };
+        assert_eq!(seeded(42), seeded(42));
+        assert_eq!(seeded(42).rng_seed, 42);
+        // 18 types over 16 pieces, two seeds dealing the same layout would be a fluke
+        assert_ne!(seeded(42).chessboard.board, seeded(43).chessboard.board);
+    }
+
+    #[test]
+    fn test_missed_move_passes_turn() {
+        let mut chess_state = ChessState::new_with_settings(Settings::new(false, false, true));
+        chess_state.move_piece(1, 4, 3, 4);
+        chess_state.move_piece(6, 3, 4, 3);
+        // find a seed whose roll for this turn is a miss
+        chess_state.rng_seed = (0..)
+            .find(|seed| MoveRolls::roll(*seed, chess_state.turn_count).miss)
+            .unwrap();
+        let board_before = chess_state.chessboard.board;
+        assert!(chess_state.move_piece(3, 4, 4, 3));
+        assert_eq!(chess_state.chessboard.board, board_before);
+        assert_eq!(chess_state.player, Player::Black);
+        assert_eq!(chess_state.info_message, Some(InfoMessage::Missed));
+    }
+
+    #[test]
+    fn test_rolls_ignored_with_default_settings() {
+        let mut chess_state = ChessState::new();
+        chess_state.move_piece(1, 4, 3, 4);
+        chess_state.move_piece(6, 3, 4, 3);
+        // even a seed that rolls a miss can't miss when misses are off
+        chess_state.rng_seed = (0..)
+            .find(|seed| MoveRolls::roll(*seed, chess_state.turn_count).miss)
+            .unwrap();
+        assert!(chess_state.move_piece(3, 4, 4, 3));
+        assert!(!chess_state.chessboard.history.last_move().unwrap().missed);
+    }
+
+    #[test]
+    fn test_settings_default_when_missing_from_save() {
+        let chess_state = ChessState::new_with_settings(Settings::new(true, true, true));
+        let mut json = serde_json::to_value(&chess_state).unwrap();
+        json.as_object_mut().unwrap().remove("settings");
+        let loaded: ChessState = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.settings, Settings::default());
+    }
+
+    #[test]
+    fn test_fools_mate_is_checkmate() {
+        let mut chess_state =
+            ChessState::new_with_board(ChessBoard::new_normal_type_only(), Settings::default());
+        chess_state.move_piece(1, 5, 2, 5);
+        chess_state.move_piece(6, 4, 4, 4);
+        chess_state.move_piece(1, 6, 3, 6);
+        assert_eq!(chess_state.winner, Winner::NoneYet);
+        chess_state.move_piece(7, 3, 3, 7);
+        assert_eq!(chess_state.winner, Winner::Black);
+        assert!(chess_state.all_legal_moves().is_empty());
+    }
+
+    #[test]
+    fn test_check_that_can_be_blocked_is_not_checkmate() {
+        let mut chess_state =
+            ChessState::new_with_board(ChessBoard::new_normal_type_only(), Settings::default());
+        chess_state.move_piece(1, 4, 3, 4);
+        chess_state.move_piece(6, 3, 4, 3);
+        // bishop checks the king, which has nowhere to go but can be covered
+        chess_state.move_piece(0, 5, 4, 1);
+        assert!(chess_state.chessboard.is_king_in_check(Player::Black));
+        assert_eq!(chess_state.winner, Winner::NoneYet);
+        // every legal move has to get out of check
+        let moves = chess_state.all_legal_moves();
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|m| m.to_row == 6 || m.to_row == 5));
+    }
+
+    #[test]
+    fn test_stalemate_is_a_tie() {
+        let mut chess_state = empty_state(Player::White);
+        chess_state.chessboard.board[7][7] = normal_piece(ChessPieceType::BlackKing);
+        chess_state.chessboard.board[0][0] = normal_piece(ChessPieceType::WhiteKing);
+        chess_state.chessboard.board[4][6] = normal_piece(ChessPieceType::WhiteQueen);
+        // queen to g6 leaves the black king without a move, but not in check
+        assert!(chess_state.move_piece(4, 6, 5, 6));
+        assert!(!chess_state.chessboard.is_king_in_check(Player::Black));
+        assert_eq!(chess_state.winner, Winner::Tie);
+    }
+
+    #[test]
+    fn test_super_effective_piece_without_moves_passes_turn() {
+        let mut chess_state = empty_state(Player::White);
+        chess_state.chessboard.board[0][0] = normal_piece(ChessPieceType::WhiteKing);
+        chess_state.chessboard.board[7][7] = normal_piece(ChessPieceType::BlackKing);
+        chess_state.chessboard.board[6][0] = normal_piece(ChessPieceType::BlackPawn);
+        // a water pawn takes a fire pawn on the last rank but one, and is then stuck
+        chess_state.chessboard.board[5][1] = Piece {
+            piece_type: ChessPieceType::WhitePawn,
+            pokemon_type: PokemonType::Water,
+        };
+        chess_state.chessboard.board[6][0].pokemon_type = PokemonType::Fire;
+        chess_state.chessboard.board[7][0] = normal_piece(ChessPieceType::BlackRook);
+        assert!(chess_state.move_piece(5, 1, 6, 0));
+        assert_eq!(
+            chess_state.info_message,
+            Some(InfoMessage::SuperEffectiveNoMovesAvailable)
+        );
+        // black isn't stuck behind white's super effective piece
+        assert_eq!(chess_state.player, Player::Black);
+        assert_eq!(chess_state.winner, Winner::NoneYet);
+        assert!(!chess_state.get_valid_moves(7, 7).is_empty());
+    }
+
+    #[test]
+    fn test_threefold_repetition_is_a_draw() {
+        let mut chess_state =
+            ChessState::new_with_board(ChessBoard::new_normal_type_only(), Settings::default());
+        for _ in 0..2 {
+            assert_eq!(chess_state.winner, Winner::NoneYet);
+            chess_state.move_piece(0, 6, 2, 5);
+            chess_state.move_piece(7, 6, 5, 5);
+            chess_state.move_piece(2, 5, 0, 6);
+            chess_state.move_piece(5, 5, 7, 6);
+        }
+        assert_eq!(chess_state.winner, Winner::Tie);
+        assert_eq!(
+            chess_state.info_message,
+            Some(InfoMessage::ThreefoldRepetition)
+        );
+    }
+
+    #[test]
+    fn test_repetition_depends_on_pokemon_types() {
+        let board = ChessBoard::new_normal_type_only();
+        let mut retyped_board = board.clone();
+        retyped_board.board[0][6].pokemon_type = PokemonType::Fire;
+        assert_ne!(
+            board.position_key(Player::White),
+            retyped_board.position_key(Player::White)
+        );
+    }
+
+    #[test]
+    fn test_fifty_move_rule_is_a_draw() {
+        let mut chess_state =
+            ChessState::new_with_board(ChessBoard::new_normal_type_only(), Settings::default());
+        chess_state.chessboard.history.halfmove_clock = 98;
+        chess_state.move_piece(0, 6, 2, 5);
+        assert_eq!(chess_state.winner, Winner::NoneYet);
+        chess_state.move_piece(7, 6, 5, 5);
+        assert_eq!(chess_state.winner, Winner::Tie);
+        assert_eq!(chess_state.info_message, Some(InfoMessage::FiftyMoveRule));
+        // knight moves never reset the count
+        assert_eq!(chess_state.chessboard.history.halfmove_clock, 100);
+    }
+
+    #[test]
+    fn test_insufficient_material_is_a_draw() {
+        let mut chess_state = empty_state(Player::Black);
+        chess_state.chessboard.board[0][0] = normal_piece(ChessPieceType::WhiteKing);
+        chess_state.chessboard.board[7][7] = normal_piece(ChessPieceType::BlackKing);
+        chess_state.chessboard.board[2][2] = normal_piece(ChessPieceType::WhiteKnight);
+        chess_state.chessboard.board[5][5] = normal_piece(ChessPieceType::BlackRook);
+        // the rook's gone after the knight takes it, leaving a lone knight
+        chess_state.player = Player::White;
+        assert!(chess_state.move_piece(2, 2, 4, 3));
+        assert!(chess_state.move_piece(7, 7, 7, 6));
+        assert!(chess_state.move_piece(4, 3, 5, 5));
+        assert_eq!(chess_state.winner, Winner::Tie);
+        assert_eq!(
+            chess_state.info_message,
+            Some(InfoMessage::InsufficientMaterial)
+        );
+    }
+
+    #[test]
+    fn test_king_that_could_destroy_itself_is_not_insufficient_material() {
+        let mut chess_state = empty_state(Player::White);
+        chess_state.chessboard.board[0][0] = normal_piece(ChessPieceType::WhiteKing);
+        chess_state.chessboard.board[7][7] = normal_piece(ChessPieceType::BlackKing);
+        chess_state.chessboard.board[2][2] = Piece {
+            piece_type: ChessPieceType::WhiteKnight,
+            pokemon_type: PokemonType::Rock,
+        };
+        // a normal type king taking a rock knight destroys itself
+        assert!(!chess_state.chessboard.has_insufficient_material());
+        chess_state.chessboard.board[2][2].pokemon_type = PokemonType::Normal;
+        assert!(chess_state.chessboard.has_insufficient_material());
+    }
+
+    #[test]
+    fn test_resign() {
+        let mut chess_state = ChessState::new();
+        assert!(chess_state.resign(Player::White).is_ok());
+        assert_eq!(chess_state.winner, Winner::Black);
+        assert_eq!(chess_state.info_message, Some(InfoMessage::Resigned));
+        // no moves or second resignations once the game is over
+        assert!(!chess_state.move_piece(1, 0, 3, 0));
+        assert!(chess_state.resign(Player::Black).is_err());
+    }
+
+    #[test]
+    fn test_draw_offer() {
+        let mut chess_state = ChessState::new();
+        assert!(chess_state.offer_draw(Player::White).is_ok());
+        assert_eq!(chess_state.draw_offer, Some(Player::White));
+        assert!(chess_state.accept_draw(Player::White).is_err());
+        assert!(chess_state.decline_draw(Player::Black).is_ok());
+        assert_eq!(chess_state.draw_offer, None);
+        assert!(chess_state.accept_draw(Player::Black).is_err());
+
+        chess_state.offer_draw(Player::White).unwrap();
+        assert!(chess_state.accept_draw(Player::Black).is_ok());
+        assert_eq!(chess_state.winner, Winner::Tie);
+        assert_eq!(chess_state.info_message, Some(InfoMessage::DrawAgreed));
+    }
+
+    #[test]
+    fn test_draw_offer_lapses_after_a_move() {
+        let mut chess_state = ChessState::new();
+        chess_state.offer_draw(Player::Black).unwrap();
+        chess_state.move_piece(1, 0, 3, 0);
+        assert_eq!(chess_state.draw_offer, None);
+        assert!(chess_state.accept_draw(Player::White).is_err());
+    }
+
+    fn timed_state(time_control: TimeControl) -> ChessState {
+        let settings = Settings {
+            time_control: Some(time_control),
+            ..Settings::default()
+        };
+        return ChessState::new_with_settings(settings);
+    }
+
+    #[test]
+    fn test_clock_runs_out() {
+        let mut chess_state = timed_state(TimeControl::new(1000, 0, None));
+        chess_state.move_piece(1, 0, 3, 0);
+        chess_state.update_clock(0);
+        assert!(!chess_state.flag_if_out_of_time(999));
+        assert!(chess_state.flag_if_out_of_time(1000));
+        assert_eq!(chess_state.winner, Winner::White);
+        assert_eq!(chess_state.info_message, Some(InfoMessage::OutOfTime));
+        assert_eq!(chess_state.clock.unwrap().running, None);
+    }
+
+    #[test]
+    fn test_clock_increment_and_delay() {
+        let mut chess_state = timed_state(TimeControl::new(10_000, 2_000, Some(1_000)));
+        // white's first move starts black's clock
+        chess_state.move_piece(1, 0, 3, 0);
+        chess_state.update_clock(0);
+        // black thinks for 4 seconds, the first one is free and then gets 2 back
+        chess_state.move_piece(6, 0, 4, 0);
+        chess_state.update_clock(4_000);
+        let clock = chess_state.clock.unwrap();
+        assert_eq!(clock.remaining_ms(Player::Black, 4_000), 9_000);
+        assert_eq!(clock.running, Some(Player::White));
+        assert_eq!(clock.remaining_ms(Player::White, 5_500), 9_500);
+    }
+
+    #[test]
+    fn test_clock_keeps_running_through_super_effective_extra_move() {
+        let settings = Settings {
+            time_control: Some(TimeControl::new(10_000, 2_000, None)),
+            ..Settings::default()
+        };
+        let fen = "4k3/8/8/3p{Fire}4/4P{Water}3/8/8/4K3 b - - 0 1";
+        let mut chess_state = ChessState::from_fen(fen, settings).unwrap();
+        // black's move starts white's clock
+        assert!(chess_state.move_piece(7, 4, 7, 5));
+        chess_state.update_clock(0);
+        // water takes fire, white moves again on the same turn, without an increment
+        assert!(chess_state.move_piece(3, 4, 4, 3));
+        assert_eq!(chess_state.info_message, Some(InfoMessage::SuperEffective));
+        assert_eq!(chess_state.player, Player::White);
+        chess_state.update_clock(3_000);
+        let clock = chess_state.clock.unwrap();
+        assert_eq!(clock.running, Some(Player::White));
+        assert_eq!(clock.remaining_ms(Player::White, 3_000), 7_000);
+        assert_eq!(clock.remaining_ms(Player::Black, 3_000), 10_000);
+        // the increment comes once the turn is over
+        assert!(chess_state.move_piece(4, 3, 5, 3));
+        chess_state.update_clock(4_000);
+        let clock = chess_state.clock.unwrap();
+        assert_eq!(clock.running, Some(Player::Black));
+        assert_eq!(clock.remaining_ms(Player::White, 4_000), 8_000);
+    }
+
+    #[test]
+    fn test_seed_is_kept_from_the_players_until_the_game_is_over() {
+        let settings = Settings {
+            seed: Some(987_654_321),
+            ..Settings::new(false, true, true)
+        };
+        let mut chess_state = ChessState::new_with_settings(settings);
+        let for_players = chess_state.for_players();
+        assert_eq!((for_players.rng_seed, for_players.settings.seed), (0, None));
+        let json = serde_json::to_string(&for_players).unwrap();
+        assert!(!json.contains("987654321"));
+
+        chess_state.resign(Player::White).unwrap();
+        let for_players = chess_state.for_players();
+        assert_eq!(for_players.rng_seed, 987_654_321);
+        assert_eq!(for_players.settings.seed, Some(987_654_321));
+    }
 }
@@ -29,6 +29,10 @@ impl ChessStateHistory {
         return None;
     }
 
+    pub fn get_current_state_mut(&mut self) -> Option<&mut ChessState> {
+        return self.state_history.get_mut(self.current_state_index);
+    }
+
     pub fn add_state(&mut self, state: ChessState) -> &ChessStateHistory {
         // cut off states after the current state
         self.state_history.truncate(self.current_state_index + 1);
@@ -38,6 +42,17 @@ impl ChessStateHistory {
         return self;
     }
 
+    // the state get_previous_state would move back to, without moving
+    pub fn peek_previous_state(&self) -> Option<ChessState> {
+        if self.current_state_index > 0 {
+            return self
+                .state_history
+                .get(self.current_state_index - 1)
+                .cloned();
+        }
+        return None;
+    }
+
     pub fn get_previous_state(&mut self) -> Option<ChessState> {
         let current_state_index: usize = self.current_state_index;
         if current_state_index > 0 {
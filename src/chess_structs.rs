@@ -1,3 +1,4 @@
+use crate::settings::{Settings, TimeControl};
 use serde::{Deserialize, Serialize};
 
 pub const BOARD_SIZE: usize = 8;
@@ -19,6 +20,9 @@ pub struct ChessHistory {
     pub has_white_king_side_rook_moved: bool,
     pub has_black_queen_side_rook_moved: bool,
     pub has_black_king_side_rook_moved: bool,
+    // plies since the last capture or pawn move, for the fifty-move rule
+    #[serde(default)]
+    pub halfmove_clock: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -35,6 +39,46 @@ pub struct ChessState {
     pub info_message: Option<InfoMessage>,
     pub require_piece_selection: bool,
     pub turn_count: usize,
+    // seeds the per-turn rolls (critical hits, misses), so replaying a move gives the same outcome
+    #[serde(default)]
+    pub rng_seed: u64,
+    // the rules this game is played with, older saves without it get the default rules
+    #[serde(default)]
+    pub settings: Settings,
+    // position keys since the last capture or pawn move, for threefold repetition
+    #[serde(default)]
+    pub position_history: Vec<String>,
+    // the player with a draw offer on the table, it lapses with the next move
+    #[serde(default)]
+    pub draw_offer: Option<Player>,
+    // None when the game is played without a time control
+    #[serde(default)]
+    pub clock: Option<ChessClock>,
+    // the types still to pick while the players draft, None once the game is under way
+    #[serde(default)]
+    pub draft: Option<Draft>,
+    // set on the copies the AI searches, see ai::search_state. Moves come from
+    // all_legal_moves and mate or stalemate is found when a position has none,
+    // so neither is worked out again for every move played
+    #[serde(skip)]
+    pub in_search: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Draft {
+    pub white_types: Vec<PokemonType>,
+    pub black_types: Vec<PokemonType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChessClock {
+    pub time_control: TimeControl,
+    pub white_remaining_ms: u64,
+    pub black_remaining_ms: u64,
+    // whose clock is counting down, None before the first move and after the game ends
+    pub running: Option<Player>,
+    // unix time in ms the running player's turn started, clients count down from here
+    pub turn_started_at_ms: u64,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Copy)]
@@ -43,6 +87,15 @@ pub enum InfoMessage {
     SuperEffectiveNoMovesAvailable,
     NotVeryEffective,
     NoEffect,
+    CriticalHit,
+    Missed,
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+    Resigned,
+    DrawAgreed,
+    OutOfTime,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Copy)]
@@ -94,6 +147,22 @@ pub struct Move {
     pub type_interaction: Option<InteractionType>,
     pub capture: Option<Capture>,
     pub castle: Option<Castle>,
+    // a critical hit lands a NotVeryEffective or NoEffect capture as a normal one
+    #[serde(default)]
+    pub critical_hit: bool,
+    // a missed capture leaves both pieces where they were and passes the turn
+    #[serde(default)]
+    pub missed: bool,
+}
+
+/**
+ * The outcome of the random rolls for a single move, decided before the move is executed.
+ * Whether a roll takes effect is up to the game's settings
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveRolls {
+    pub critical_hit: bool,
+    pub miss: bool,
 }
 
 pub struct Position {
@@ -1,35 +1,310 @@
 use crate::game::Game;
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
-use tokio::fs::{File, OpenOptions};
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::fs::{self, File, OpenOptions};
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 
-pub async fn save_game(game: Game) -> Result<(), Box<dyn std::error::Error>> {
-    let directory = String::from("games/");
-    let full_path = directory + &game.name + ".pchess";
-    let mut file = File::create(&full_path).await?;
+const GAMES_DIRECTORY: &str = "games/";
+const GAMES_DATABASE: &str = "games.db";
 
-    // Convert the board to a JSON string
-    let j = serde_json::to_string(&game)?;
+pub type StoreError = Box<dyn Error + Send + Sync>;
 
-    // Write the JSON string to the file
-    file.write_all(j.as_bytes()).await?;
-    file.flush().await?; // Explicitly flush the file buffer
+/**
+ * Where games are kept between messages. Game::save and Game::load go through the store
+ * configured at startup, see configure_game_store
+ */
+#[async_trait]
+pub trait GameStore: Send + Sync {
+    async fn save(&self, game: &Game, kind: SaveKind) -> Result<(), StoreError>;
+    async fn load(&self, name: &str) -> Result<Game, StoreError>;
 
-    Ok(())
+    // writes out anything held back, stores that write straight away have nothing to do
+    async fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+// whether a save starts a game or carries on with one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveKind {
+    NewGame,
+    Update,
+}
+
+// a save check_version turned down, trying it again won't get it in
+#[derive(Debug)]
+pub struct VersionConflict(pub String);
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for VersionConflict {}
+
+/**
+ * Saves have to be newer than the stored version, so a change made to a copy that was
+ * loaded before someone else's save can't overwrite it. Versions can be skipped,
+ * a cache in front of the store only writes the latest. A new game can't take the
+ * name of one that's already stored
+ */
+pub fn check_version(
+    name: &str,
+    stored_version: Option<u64>,
+    version: u64,
+    kind: SaveKind,
+) -> Result<(), StoreError> {
+    return match (stored_version, kind) {
+        (Some(_), SaveKind::NewGame) => {
+            Err(VersionConflict(format!("There's already a game called {}", name)).into())
+        }
+        (Some(stored_version), SaveKind::Update) if stored_version >= version => {
+            Err(VersionConflict(format!(
+                "{} is at version {}, saving version {} would overwrite a newer save",
+                name, stored_version, version
+            ))
+            .into())
+        }
+        _ => Ok(()),
+    };
+}
+
+// only the version is read back to check a save against
+#[derive(Deserialize)]
+struct StoredVersion {
+    #[serde(default)]
+    version: u64,
+}
+
+fn stored_version(json: &str) -> Result<u64, StoreError> {
+    return Ok(serde_json::from_str::<StoredVersion>(json)?.version);
+}
+
+static GAME_STORE: OnceLock<Box<dyn GameStore>> = OnceLock::new();
+
+// can only be set once, before the first game is saved or loaded
+pub fn configure_game_store(store: Box<dyn GameStore>) -> Result<(), String> {
+    return GAME_STORE
+        .set(store)
+        .map_err(|_| "The game store is already configured".to_string());
+}
+
+// the configured store, the games/ directory if none was configured
+pub fn game_store() -> &'static dyn GameStore {
+    return GAME_STORE
+        .get_or_init(|| Box::new(FileStore::new(GAMES_DIRECTORY)))
+        .as_ref();
+}
+
+/**
+ * The store picked with the GAME_STORE environment variable, file (the default),
+ * sqlite or memory. GAME_STORE_PATH is the directory or database file to use
+ */
+pub fn game_store_from_env() -> Result<Box<dyn GameStore>, String> {
+    let kind = env::var("GAME_STORE").unwrap_or("file".to_string());
+    let path = env::var("GAME_STORE_PATH").ok();
+    return match kind.as_str() {
+        "file" => Ok(Box::new(FileStore::new(
+            path.as_deref().unwrap_or(GAMES_DIRECTORY),
+        ))),
+        "sqlite" => {
+            let store = SqliteStore::open(path.as_deref().unwrap_or(GAMES_DATABASE))
+                .map_err(|err| format!("Failed to open the game database: {}", err))?;
+            Ok(Box::new(store))
+        }
+        "memory" => Ok(Box::new(MemoryStore::new())),
+        _ => Err(format!(
+            "{} isn't a game store, use file, sqlite or memory",
+            kind
+        )),
+    };
+}
+
+// one JSON file per game
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        FileStore {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        return self.directory.join(format!("{}.pchess", name));
+    }
+}
+
+#[async_trait]
+impl GameStore for FileStore {
+    /**
+     * Writes a temporary file next to the game and renames it over the old one,
+     * a crash halfway through leaves the last save as it was
+     */
+    async fn save(&self, game: &Game, kind: SaveKind) -> Result<(), StoreError> {
+        let path = self.path(&game.name);
+        let stored_version = match fs::read_to_string(&path).await {
+            Ok(contents) => Some(stored_version(&contents)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        check_version(&game.name, stored_version, game.version, kind)?;
+
+        // the compact format, see save_format.rs
+        let j = game.to_save()?;
+
+        // concurrent saves of the same game each get their own temporary file
+        let temp_path = path.with_extension(format!("pchess.{}.tmp", rand::random::<u32>()));
+        let mut file = File::create(&temp_path).await?;
+        file.write_all(j.as_bytes()).await?;
+        // on disk before it replaces the old file, not just flushed to the OS
+        file.sync_all().await?;
+        drop(file);
+        if let Err(err) = fs::rename(&temp_path, &path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    async fn load(&self, name: &str) -> Result<Game, StoreError> {
+        let mut file = File::open(self.path(name)).await?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+
+        // either save format, see save_format.rs
+        let game = Game::from_save(&contents)?;
+
+        Ok(game)
+    }
+}
+
+// keeps games for as long as the server runs, for tests and trying things out
+pub struct MemoryStore {
+    games: Mutex<HashMap<String, Game>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            games: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+#[async_trait]
+impl GameStore for MemoryStore {
+    async fn save(&self, game: &Game, kind: SaveKind) -> Result<(), StoreError> {
+        let mut games = self.games.lock().unwrap();
+        let stored_version = games.get(&game.name).map(|stored| stored.version);
+        check_version(&game.name, stored_version, game.version, kind)?;
+        games.insert(game.name.clone(), game.clone());
+        Ok(())
+    }
+
+    async fn load(&self, name: &str) -> Result<Game, StoreError> {
+        return match self.games.lock().unwrap().get(name) {
+            Some(game) => Ok(game.clone()),
+            None => Err(format!("No game called {}", name).into()),
+        };
+    }
+}
+
+// a games table in an SQLite database, one row per game with the game as JSON
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
 }
 
-pub async fn load_game(name: &String) -> Result<Game, Box<dyn std::error::Error>> {
-    let directory = String::from("games/");
-    let full_path = directory + &name + ".pchess";
-    let mut file = File::open(&full_path).await?;
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, rusqlite::Error> {
+        return SqliteStore::with_connection(Connection::open(path)?);
+    }
 
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).await?;
+    pub fn open_in_memory() -> Result<Self, rusqlite::Error> {
+        return SqliteStore::with_connection(Connection::open_in_memory()?);
+    }
 
-    // Deserialize the JSON string to a ChessState
-    let game: Game = serde_json::from_str(&contents)?;
+    fn with_connection(connection: Connection) -> Result<Self, rusqlite::Error> {
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS games (name TEXT PRIMARY KEY, game TEXT NOT NULL)",
+            (),
+        )?;
+        return Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+        });
+    }
 
-    Ok(game)
+    // SQLite blocks, so the queries run off the async threads
+    async fn with_database<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+    ) -> Result<T, StoreError> {
+        let connection = self.connection.clone();
+        return tokio::task::spawn_blocking(move || query(&mut connection.lock().unwrap())).await?;
+    }
+}
+
+#[async_trait]
+impl GameStore for SqliteStore {
+    async fn save(&self, game: &Game, kind: SaveKind) -> Result<(), StoreError> {
+        let name = game.name.clone();
+        let json = game.to_save()?;
+        let game_version = game.version;
+        return self
+            .with_database(move |connection| {
+                // the version check and the write go in together or not at all
+                let transaction = connection.transaction()?;
+                let stored: Option<String> = transaction
+                    .query_row("SELECT game FROM games WHERE name = ?1", [&name], |row| {
+                        row.get(0)
+                    })
+                    .optional()?;
+                let stored_version = stored.as_deref().map(stored_version).transpose()?;
+                check_version(&name, stored_version, game_version, kind)?;
+                transaction.execute(
+                    "INSERT INTO games (name, game) VALUES (?1, ?2)
+                     ON CONFLICT(name) DO UPDATE SET game = excluded.game",
+                    (&name, &json),
+                )?;
+                transaction.commit()?;
+                Ok(())
+            })
+            .await;
+    }
+
+    async fn load(&self, name: &str) -> Result<Game, StoreError> {
+        let name = name.to_string();
+        let json: String = self
+            .with_database(move |connection| {
+                let json = connection.query_row(
+                    "SELECT game FROM games WHERE name = ?1",
+                    [&name],
+                    |row| row.get(0),
+                )?;
+                Ok(json)
+            })
+            .await?;
+        return Ok(Game::from_save(&json)?);
+    }
 }
 
 pub async fn read_names_from_file() -> Result<Vec<String>, Box<dyn Error>> {
@@ -63,3 +338,67 @@ pub async fn write_name_to_file(name: String) -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_state_history::ChessStateHistory;
+    use crate::chess_structs::ChessState;
+
+    fn game(name: &str) -> Game {
+        let chess_state = ChessState::new();
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        return Game::new(name.to_string(), chess_state_history);
+    }
+
+    // every store has to give back exactly what it was given, and nothing it wasn't
+    async fn check_store(store: &dyn GameStore) {
+        let mut saved = game("store_test");
+        saved.version = 1;
+        store.save(&saved, SaveKind::NewGame).await.unwrap();
+        let loaded = store.load("store_test").await.unwrap();
+        assert_eq!(loaded, saved);
+        assert!(store.load("missing").await.is_err());
+
+        // two copies of the same version, the second save to land is stale
+        let mut first = loaded.clone();
+        let mut second = loaded;
+        first.move_piece(1, 4, 3, 4);
+        first.version += 1;
+        second.move_piece(1, 3, 3, 3);
+        second.version += 1;
+        store.save(&first, SaveKind::Update).await.unwrap();
+        assert!(store.save(&second, SaveKind::Update).await.is_err());
+        assert_eq!(store.load("store_test").await.unwrap(), first);
+
+        // a new game can't take the name of one in progress
+        let mut replacement = game("store_test");
+        replacement.version = 1;
+        assert!(store.save(&replacement, SaveKind::NewGame).await.is_err());
+        assert_eq!(store.load("store_test").await.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        check_store(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        check_store(&SqliteStore::open_in_memory().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let directory = env::temp_dir().join(format!("pokemon_chess_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        check_store(&FileStore::new(&directory)).await;
+        // every temporary file was renamed into place
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["store_test.pchess"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
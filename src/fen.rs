@@ -0,0 +1,501 @@
+// FEN, extended with Pokémon types, for setting up positions and sharing them.
+//
+// Each piece letter may be followed by its type in braces, pieces without one are Normal:
+//     r{Water}n{Fire}b{Grass}q{Ghost}k{Normal}bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1
+// After the usual six fields an optional seventh names the square of the piece
+// that is owed a super effective extra move.
+
+use crate::chess_structs::{
+    ChessBoard, ChessHistory, ChessPieceType, ChessState, InteractionType, Move, Piece, Player,
+    PokemonType, BOARD_SIZE,
+};
+use crate::settings::Settings;
+
+pub const START_POSITION: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+impl ChessState {
+    pub fn from_fen(fen: &str, settings: Settings) -> Result<ChessState, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 || fields.len() > 7 {
+            return Err(
+                "A position needs the board, side to move, castling and en passant fields"
+                    .to_string(),
+            );
+        }
+        let board = parse_placement(fields[0])?;
+        let player = match fields[1] {
+            "w" => Player::White,
+            "b" => Player::Black,
+            side => return Err(format!("Unknown side to move {}", side)),
+        };
+
+        let mut history = parse_castling(fields[2], &board)?;
+        history.last_move = parse_en_passant(fields[3], &board, player)?;
+        history.halfmove_clock = match fields.get(4) {
+            Some(halfmove_clock) => parse_number(halfmove_clock)?,
+            None => 0,
+        };
+        // a number too big to count the plies up to isn't a position anyone reached,
+        // and the turn count needs room to go up as the game is played on
+        let turn_count = match fields.get(5) {
+            Some(fullmove_number) => (parse_number(fullmove_number)?.max(1) - 1)
+                .checked_mul(2)
+                .filter(|turns| *turns < MAX_TURN_COUNT)
+                .and_then(|turns| turns.checked_add((player == Player::Black) as usize))
+                .ok_or(format!("{} isn't a move number", fullmove_number))?,
+            None => (player == Player::Black) as usize,
+        };
+        if let Some(square) = fields.get(6) {
+            if history.last_move.is_some() && *square != "-" {
+                return Err("A position can't owe both en passant and an extra move".to_string());
+            }
+            history.last_move = parse_super_effective(square, &board, player)?;
+        }
+
+        let mut chessboard = ChessBoard::new_normal_type_only();
+        chessboard.board = board;
+        chessboard.history = history;
+        let mut chess_state = ChessState::new_with_board(chessboard, settings);
+        chess_state.player = player;
+        chess_state.turn_count = turn_count;
+        chess_state.position_history = vec![chess_state.chessboard.position_key(player)];
+        // the position may already be decided, a mate puzzle that's been solved for example
+        let (winner, reason) = chess_state.get_winner_and_reason();
+        chess_state.winner = winner;
+        chess_state.info_message = reason;
+        return Ok(chess_state);
+    }
+
+    pub fn to_fen(&self) -> String {
+        let history = &self.chessboard.history;
+        let mut fields = vec![
+            placement(&self.chessboard),
+            match self.player {
+                Player::White => "w".to_string(),
+                Player::Black => "b".to_string(),
+            },
+            castling(history),
+            en_passant(history),
+            history.halfmove_clock.to_string(),
+            (self.turn_count / 2 + 1).to_string(),
+        ];
+        if let Some((row, col)) = history.last_move_super_effective() {
+            // once the piece had no moves the turn passed and nothing is owed
+            if self
+                .chessboard
+                .get_piece(row, col)
+                .piece_type
+                .get_piece_player()
+                == self.player
+            {
+                fields.push(square_name(row, col));
+            }
+        }
+        return fields.join(" ");
+    }
+}
+
+// plain FEN for tools that don't know about types
+pub fn without_types(fen: &str) -> String {
+    let mut plain = String::new();
+    let mut in_type = false;
+    for c in fen.chars() {
+        match c {
+            '{' => in_type = true,
+            '}' => in_type = false,
+            _ if !in_type => plain.push(c),
+            _ => {}
+        }
+    }
+    return plain;
+}
+
+// a1 is row 0, col 0, from white's side of the board
+pub fn square_name(row: usize, col: usize) -> String {
+    return format!("{}{}", (b'a' + col as u8) as char, row + 1);
+}
+
+pub fn parse_square(name: &str) -> Option<(usize, usize)> {
+    let mut chars = name.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    return Some((rank as usize - '1' as usize, file as usize - 'a' as usize));
+}
+
+// more plies than the longest game the fifty-move rule allows
+const MAX_TURN_COUNT: usize = 20_000;
+
+fn parse_number(field: &str) -> Result<usize, String> {
+    return field
+        .parse::<usize>()
+        .map_err(|_| format!("{} is not a move count", field));
+}
+
+fn parse_placement(field: &str) -> Result<[[Piece; BOARD_SIZE]; BOARD_SIZE], String> {
+    let ranks: Vec<&str> = field.split('/').collect();
+    if ranks.len() != BOARD_SIZE {
+        return Err(format!("The board needs {} ranks", BOARD_SIZE));
+    }
+    let mut board = [[Piece::empty(); BOARD_SIZE]; BOARD_SIZE];
+    // the eighth rank comes first
+    for (rank_index, rank) in ranks.iter().enumerate() {
+        let row = BOARD_SIZE - 1 - rank_index;
+        let mut col = 0;
+        let mut chars = rank.chars().peekable();
+        while let Some(letter) = chars.next() {
+            if let Some(empty_squares) = letter.to_digit(10) {
+                col += empty_squares as usize;
+                continue;
+            }
+            let piece_type =
+                ChessPieceType::from_fen_char(letter).ok_or(format!("Unknown piece {}", letter))?;
+            let mut pokemon_type = PokemonType::Normal;
+            if chars.peek() == Some(&'{') {
+                chars.next();
+                let type_name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                pokemon_type = PokemonType::from_name(&type_name)
+                    .ok_or(format!("Unknown type {}", type_name))?;
+            }
+            if col >= BOARD_SIZE {
+                return Err(format!(
+                    "Rank {} has more than {} squares",
+                    row + 1,
+                    BOARD_SIZE
+                ));
+            }
+            board[row][col] = Piece {
+                piece_type,
+                pokemon_type,
+            };
+            col += 1;
+        }
+        if col != BOARD_SIZE {
+            return Err(format!(
+                "Rank {} doesn't have {} squares",
+                row + 1,
+                BOARD_SIZE
+            ));
+        }
+    }
+
+    for player in [Player::White, Player::Black] {
+        let kings = board
+            .iter()
+            .flatten()
+            .filter(|piece| {
+                piece.piece_type.is_king() && piece.piece_type.get_piece_player() == player
+            })
+            .count();
+        if kings != 1 {
+            return Err(format!("{:?} needs exactly one king", player));
+        }
+    }
+    for row in [0, BOARD_SIZE - 1] {
+        if board[row].iter().any(|piece| {
+            matches!(
+                piece.piece_type,
+                ChessPieceType::WhitePawn | ChessPieceType::BlackPawn
+            )
+        }) {
+            return Err("Pawns can't stand on the first or last rank".to_string());
+        }
+    }
+    return Ok(board);
+}
+
+/**
+ * Castling rights are stored as which kings and rooks have moved,
+ * a right is only given if its king and rook are still on their starting squares
+ */
+fn parse_castling(
+    field: &str,
+    board: &[[Piece; BOARD_SIZE]; BOARD_SIZE],
+) -> Result<ChessHistory, String> {
+    let mut history = ChessHistory::new();
+    if field != "-" && (field.is_empty() || field.chars().any(|c| !"KQkq".contains(c))) {
+        return Err(format!("Unknown castling rights {}", field));
+    }
+    let has_right =
+        |right: char, king: ChessPieceType, rook: ChessPieceType, row: usize, rook_col: usize| {
+            if !field.contains(right) {
+                return Ok(false);
+            }
+            if board[row][4].piece_type != king || board[row][rook_col].piece_type != rook {
+                return Err(format!(
+                    "Castling right {} without the king and rook at home",
+                    right
+                ));
+            }
+            return Ok(true);
+        };
+    let white_king_side = has_right(
+        'K',
+        ChessPieceType::WhiteKing,
+        ChessPieceType::WhiteRook,
+        0,
+        7,
+    )?;
+    let white_queen_side = has_right(
+        'Q',
+        ChessPieceType::WhiteKing,
+        ChessPieceType::WhiteRook,
+        0,
+        0,
+    )?;
+    let black_king_side = has_right(
+        'k',
+        ChessPieceType::BlackKing,
+        ChessPieceType::BlackRook,
+        7,
+        7,
+    )?;
+    let black_queen_side = has_right(
+        'q',
+        ChessPieceType::BlackKing,
+        ChessPieceType::BlackRook,
+        7,
+        0,
+    )?;
+    history.has_white_king_moved = !white_king_side && !white_queen_side;
+    history.has_white_king_side_rook_moved = !white_king_side;
+    history.has_white_queen_side_rook_moved = !white_queen_side;
+    history.has_black_king_moved = !black_king_side && !black_queen_side;
+    history.has_black_king_side_rook_moved = !black_king_side;
+    history.has_black_queen_side_rook_moved = !black_queen_side;
+    return Ok(history);
+}
+
+// en passant comes from the last move, so the double pawn push is put back into the history
+fn parse_en_passant(
+    field: &str,
+    board: &[[Piece; BOARD_SIZE]; BOARD_SIZE],
+    player: Player,
+) -> Result<Option<Move>, String> {
+    if field == "-" {
+        return Ok(None);
+    }
+    let (row, col) = parse_square(field).ok_or(format!("Unknown en passant square {}", field))?;
+    let (piece_type, from_row, to_row) = match (player, row) {
+        (Player::Black, 2) => (ChessPieceType::WhitePawn, 1, 3),
+        (Player::White, 5) => (ChessPieceType::BlackPawn, 6, 4),
+        _ => return Err(format!("{} can't be an en passant square", field)),
+    };
+    if board[to_row][col].piece_type != piece_type {
+        return Err(format!("No pawn passed over {}", field));
+    }
+    return Ok(Some(Move {
+        piece_type,
+        from_row,
+        from_col: col,
+        to_row,
+        to_col: col,
+        type_interaction: Some(InteractionType::Normal),
+        capture: None,
+        castle: None,
+        critical_hit: false,
+        missed: false,
+    }));
+}
+
+// the extra move also comes from the last move, a super effective one onto the square
+fn parse_super_effective(
+    field: &str,
+    board: &[[Piece; BOARD_SIZE]; BOARD_SIZE],
+    player: Player,
+) -> Result<Option<Move>, String> {
+    if field == "-" {
+        return Ok(None);
+    }
+    let (row, col) = parse_square(field).ok_or(format!("Unknown square {}", field))?;
+    let piece_type = board[row][col].piece_type;
+    if piece_type == ChessPieceType::Empty || piece_type.get_piece_player() != player {
+        return Err(format!("The side to move has no piece on {}", field));
+    }
+    return Ok(Some(Move {
+        piece_type,
+        from_row: row,
+        from_col: col,
+        to_row: row,
+        to_col: col,
+        type_interaction: Some(InteractionType::SuperEffective),
+        capture: None,
+        castle: None,
+        critical_hit: false,
+        missed: false,
+    }));
+}
+
+fn placement(chessboard: &ChessBoard) -> String {
+    let mut ranks = Vec::new();
+    for row in (0..BOARD_SIZE).rev() {
+        let mut rank = String::new();
+        let mut empty_squares = 0;
+        for col in 0..BOARD_SIZE {
+            let piece = chessboard.get_piece(row, col);
+            if piece.piece_type == ChessPieceType::Empty {
+                empty_squares += 1;
+                continue;
+            }
+            if empty_squares > 0 {
+                rank.push_str(&empty_squares.to_string());
+                empty_squares = 0;
+            }
+            rank.push(piece.piece_type.fen_char());
+            if piece.pokemon_type != PokemonType::Normal {
+                rank.push_str(&format!("{{{}}}", piece.pokemon_type));
+            }
+        }
+        if empty_squares > 0 {
+            rank.push_str(&empty_squares.to_string());
+        }
+        ranks.push(rank);
+    }
+    return ranks.join("/");
+}
+
+fn castling(history: &ChessHistory) -> String {
+    let mut rights = String::new();
+    if history.can_castle_kingside(true) {
+        rights.push('K');
+    }
+    if history.can_castle_queenside(true) {
+        rights.push('Q');
+    }
+    if history.can_castle_kingside(false) {
+        rights.push('k');
+    }
+    if history.can_castle_queenside(false) {
+        rights.push('q');
+    }
+    if rights.is_empty() {
+        rights.push('-');
+    }
+    return rights;
+}
+
+fn en_passant(history: &ChessHistory) -> String {
+    return match history.last_move_enables_en_passant() {
+        // the square the pawn passed over
+        Some((3, col)) => square_name(2, col),
+        Some((_, col)) => square_name(5, col),
+        None => "-".to_string(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_position_round_trip() {
+        let chess_state = ChessState::from_fen(START_POSITION, Settings::default()).unwrap();
+        assert_eq!(
+            chess_state.chessboard.board,
+            ChessBoard::new_normal_type_only().board
+        );
+        assert_eq!(chess_state.to_fen(), START_POSITION);
+    }
+
+    #[test]
+    fn test_types_round_trip() {
+        let chess_state = ChessState::new();
+        let fen = chess_state.to_fen();
+        let parsed = ChessState::from_fen(&fen, Settings::default()).unwrap();
+        assert_eq!(parsed.chessboard.board, chess_state.chessboard.board);
+        assert_eq!(parsed.to_fen(), fen);
+    }
+
+    #[test]
+    fn test_type_suffix() {
+        let chess_state = ChessState::from_fen(
+            "4k3/8/8/8/8/8/8/R{Fire}3K{ghost}3 w Q - 0 1",
+            Settings::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            chess_state.chessboard.board[0][0].pokemon_type,
+            PokemonType::Fire
+        );
+        assert_eq!(
+            chess_state.chessboard.board[0][4].pokemon_type,
+            PokemonType::Ghost
+        );
+        assert_eq!(
+            chess_state.chessboard.board[7][4].pokemon_type,
+            PokemonType::Normal
+        );
+        assert!(chess_state.chessboard.history.can_castle_queenside(true));
+        assert!(!chess_state.chessboard.history.can_castle_kingside(true));
+        assert!(!chess_state.chessboard.history.can_castle_queenside(false));
+    }
+
+    #[test]
+    fn test_en_passant_square() {
+        let fen = "4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 12";
+        let chess_state = ChessState::from_fen(fen, Settings::default()).unwrap();
+        assert_eq!(chess_state.player, Player::Black);
+        assert_eq!(chess_state.turn_count, 23);
+        let moves = chess_state.get_valid_moves(3, 3);
+        assert!(moves.iter().any(|m| (m.to_row, m.to_col) == (2, 4)));
+        assert_eq!(chess_state.to_fen(), fen);
+    }
+
+    #[test]
+    fn test_super_effective_extra_move() {
+        let fen = "4k3/8/8/8/8/8/P7/1N2K3 w - - 0 1 b1";
+        let chess_state = ChessState::from_fen(fen, Settings::default()).unwrap();
+        // only the knight may move
+        assert!(chess_state.get_valid_moves(1, 0).is_empty());
+        assert!(!chess_state.get_valid_moves(0, 1).is_empty());
+        assert_eq!(chess_state.to_fen(), fen);
+    }
+
+    #[test]
+    fn test_finished_position() {
+        // back rank mate, black to move has lost
+        let chess_state =
+            ChessState::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1", Settings::default()).unwrap();
+        assert_eq!(
+            chess_state.get_winner(),
+            crate::chess_structs::Winner::White
+        );
+        assert_eq!(chess_state.winner, crate::chess_structs::Winner::White);
+    }
+
+    #[test]
+    fn test_invalid_positions() {
+        let invalid = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq -",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq -",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -",
+            "rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ -",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR{Shadow} w KQkq -",
+            "4k3/8/8/8/8/8/8/4K3 w K -",
+            "4k3/8/8/8/8/8/8/4K3 w - e3",
+            "P3k3/8/8/8/8/8/8/4K3 w - -",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 x",
+        ];
+        for fen in invalid {
+            assert!(
+                ChessState::from_fen(fen, Settings::default()).is_err(),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn test_fullmove_number_too_big_to_count() {
+        let fen = format!("4k3/8/8/8/8/8/8/4K3 b - - 0 {}", usize::MAX);
+        let error = ChessState::from_fen(&fen, Settings::default()).unwrap_err();
+        assert!(error.contains("isn't a move number"), "{}", error);
+        let fen = format!("4k3/8/8/8/8/8/8/4K3 w - - 0 {}", usize::MAX / 2 + 1);
+        assert!(ChessState::from_fen(&fen, Settings::default()).is_err());
+        let chess_state =
+            ChessState::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 9000", Settings::default()).unwrap();
+        assert_eq!(chess_state.turn_count, 17_999);
+    }
+}
@@ -1,46 +1,74 @@
+use crate::ai;
 use crate::chess_state_history::ChessStateHistory;
-use crate::chess_structs::ChessState;
-use crate::database::{load_game, save_game};
+use crate::chess_structs::{ChessClock, ChessState, Move, Player, PokemonType, Winner};
+use crate::database::{game_store, SaveKind};
 use crate::settings::Settings;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Game {
     pub name: String,
-    pub settings: Settings,
     pub chess_state_history: ChessStateHistory,
+    // how many times the game has been saved, a save has to follow on from the stored version
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Game {
-    pub fn new(name: String, settings: Settings, chess_state_history: ChessStateHistory) -> Self {
+    pub fn new(name: String, chess_state_history: ChessStateHistory) -> Self {
         Game {
             name,
-            settings,
             chess_state_history,
+            version: 0,
         }
     }
 
-    pub async fn save(&self) -> bool {
-        let result = save_game(self.clone()).await;
+    // the rules are kept with the states, every state of a game has the same ones
+    pub fn settings(&self) -> Settings {
+        return self
+            .chess_state_history
+            .get_current_state()
+            .map_or(Settings::default(), |chess_state| chess_state.settings);
+    }
+
+    /**
+     * Saves the game as its next version. The save is turned down if someone else
+     * saved the game since it was loaded
+     */
+    pub async fn save(&mut self) -> bool {
+        self.version += 1;
+        let result = game_store().save(self, SaveKind::Update).await;
         let err = result.err();
         if err.is_some() {
             println!("Failed to save game: {:?}", err);
+            self.version -= 1;
             return false;
         }
         return true;
     }
 
+    // saves a game that was just started, turned down if its name is taken
+    pub async fn save_new(&mut self) -> Result<(), String> {
+        self.version += 1;
+        let result = game_store().save(self, SaveKind::NewGame).await;
+        if let Err(err) = result {
+            self.version -= 1;
+            return Err(format!("Failed to save game: {}", err));
+        }
+        return Ok(());
+    }
+
+    pub async fn exists(name: &str) -> bool {
+        return game_store().load(name).await.is_ok();
+    }
+
     pub async fn load(name: &String) -> Self {
-        let result = load_game(name).await;
+        let result = game_store().load(name).await;
         match result {
             Ok(game) => return game,
             Err(err) => println!("Failed to load game: {:?}", err),
         }
-        return Game::new(
-            String::new(),
-            Settings::new(false, false, false),
-            ChessStateHistory::new(),
-        );
+        return Game::new(String::new(), ChessStateHistory::new());
     }
 
     pub fn get_current_state(&self) -> Option<ChessState> {
@@ -54,9 +82,16 @@ impl Game {
         to_row: usize,
         to_col: usize,
     ) -> bool {
+        let now_ms = ChessClock::now_ms();
         let mut chess_state = self.get_current_state().unwrap().clone();
+        // a move that comes in too late loses on time instead
+        if chess_state.flag_if_out_of_time(now_ms) {
+            self.chess_state_history.add_state(chess_state);
+            return true;
+        }
         let change_made = chess_state.move_piece(from_row, from_col, to_row, to_col);
         if change_made {
+            chess_state.update_clock(now_ms);
             self.chess_state_history.add_state(chess_state);
             return true;
         }
@@ -64,11 +99,162 @@ impl Game {
     }
 
     pub fn select_pawn_promotion_piece(&mut self, piece_str: String) -> Result<(), String> {
+        let now_ms = ChessClock::now_ms();
         let mut chess_state = self.get_current_state().unwrap().clone();
+        if chess_state.flag_if_out_of_time(now_ms) {
+            self.chess_state_history.add_state(chess_state);
+            return Err("Out of time".to_string());
+        }
         let result = chess_state.select_pawn_promotion_piece(piece_str);
         if result.is_err() {
             return Err(result.unwrap_err());
         }
+        chess_state.update_clock(now_ms);
+        self.chess_state_history.add_state(chess_state);
+        return Ok(());
+    }
+
+    /**
+     * Plays a move given in SAN or UCI, Nxe5 or e7e8q. A promotion in the notation
+     * is selected straight away, without one the pawn waits for
+     * select_pawn_promotion_piece like any other move
+     */
+    pub fn move_by_notation(&mut self, notation: &str) -> Result<(), String> {
+        let chess_state = self.get_current_state().unwrap();
+        let (m, promotion) = Move::from_notation(notation, &chess_state)?;
+        if promotion.is_some() && !m.is_promotion() {
+            return Err(format!("{} doesn't promote", notation));
+        }
+        if !self.move_piece(m.from_row, m.from_col, m.to_row, m.to_col) {
+            return Err(format!("{} can't be played", notation));
+        }
+        if let Some(promotion) = promotion {
+            if self.get_current_state().unwrap().require_piece_selection {
+                self.select_pawn_promotion_piece(promotion)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /**
+     * Plays the AI's moves until it's the other player's turn or the game is over,
+     * a super effective move gives the AI another move straight away.
+     * While drafting, the AI makes its picks the same way
+     * Returns true if the AI moved
+     */
+    pub fn play_ai_turns(&mut self) -> bool {
+        let ai_player = match self.settings().ai_player {
+            Some(ai_player) => ai_player,
+            None => return false,
+        };
+        let mut moved = false;
+        loop {
+            let chess_state = self.get_current_state().unwrap();
+            if chess_state.winner != Winner::NoneYet || chess_state.player != ai_player {
+                return moved;
+            }
+            if chess_state.is_drafting() {
+                let (row, col, pokemon_type) = match ai::draft_pick(&chess_state) {
+                    Some(pick) => pick,
+                    None => return moved,
+                };
+                if self.draft_type(row, col, pokemon_type).is_err() {
+                    return moved;
+                }
+                moved = true;
+                continue;
+            }
+            let limits = self.settings().ai_difficulty.search_limits();
+            let m = match ai::best_move(&chess_state, &limits, &mut rand::thread_rng()) {
+                Some(m) => m,
+                None => return moved,
+            };
+            if !self.move_piece(m.from_row, m.from_col, m.to_row, m.to_col) {
+                return moved;
+            }
+            moved = true;
+            if self.get_current_state().unwrap().require_piece_selection {
+                let _ = self.select_pawn_promotion_piece(ai::promotion_piece());
+            }
+        }
+    }
+
+    // analysis of the current state, the history is left as it is
+    pub fn get_hints(&self, count: usize) -> Vec<ai::Hint> {
+        return match self.get_current_state() {
+            Some(chess_state) => ai::hints(&chess_state, count),
+            None => vec![],
+        };
+    }
+
+    // used by the room's timer, returns true if the player to move lost on time
+    pub fn flag_if_out_of_time(&mut self) -> bool {
+        let mut chess_state = self.get_current_state().unwrap();
+        if chess_state.flag_if_out_of_time(ChessClock::now_ms()) {
+            self.chess_state_history.add_state(chess_state);
+            return true;
+        }
+        return false;
+    }
+
+    pub fn time_until_flag_ms(&self) -> Option<u64> {
+        let chess_state = self.get_current_state()?;
+        return chess_state.clock?.time_until_flag_ms(ChessClock::now_ms());
+    }
+
+    pub fn draft_type(
+        &mut self,
+        row: usize,
+        col: usize,
+        pokemon_type: PokemonType,
+    ) -> Result<(), String> {
+        return self
+            .update_current_state(|chess_state| chess_state.draft_type(row, col, pokemon_type));
+    }
+
+    pub fn resign(&mut self, player: Player) -> Result<(), String> {
+        return self.update_current_state(|chess_state| chess_state.resign(player));
+    }
+
+    // an offer to the AI is answered straight away, nobody else would answer it
+    pub fn offer_draw(&mut self, player: Player) -> Result<(), String> {
+        self.update_current_state(|chess_state| chess_state.offer_draw(player))?;
+        self.answer_draw_offer_for_ai();
+        return Ok(());
+    }
+
+    fn answer_draw_offer_for_ai(&mut self) {
+        let ai_player = match self.settings().ai_player {
+            Some(ai_player) => ai_player,
+            None => return,
+        };
+        let chess_state = self.get_current_state().unwrap();
+        if chess_state.draw_offer != Some(ai_player.other_player()) {
+            return;
+        }
+        let _ = if ai::accepts_draw(&chess_state, ai_player) {
+            self.accept_draw(ai_player)
+        } else {
+            self.decline_draw(ai_player)
+        };
+    }
+
+    pub fn accept_draw(&mut self, player: Player) -> Result<(), String> {
+        return self.update_current_state(|chess_state| chess_state.accept_draw(player));
+    }
+
+    pub fn decline_draw(&mut self, player: Player) -> Result<(), String> {
+        return self.update_current_state(|chess_state| chess_state.decline_draw(player));
+    }
+
+    // apply a change to a copy of the current state and keep it in the history if it worked
+    fn update_current_state(
+        &mut self,
+        update: impl FnOnce(&mut ChessState) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let mut chess_state = self.get_current_state().unwrap();
+        update(&mut chess_state)?;
+        chess_state.update_clock(ChessClock::now_ms());
         self.chess_state_history.add_state(chess_state);
         return Ok(());
     }
@@ -76,10 +262,92 @@ impl Game {
     pub fn get_previous_state(&mut self) -> Option<ChessState> {
         // if we have a previous state, return it
         // otherwise return current state
-        return self.chess_state_history.get_previous_state();
+        let live_clock = self.stop_clock();
+        self.chess_state_history.get_previous_state()?;
+        return Some(self.resume_clock(live_clock));
     }
 
     pub fn get_next_state(&mut self) -> Option<ChessState> {
-        return self.chess_state_history.get_next_state();
+        let live_clock = self.stop_clock();
+        self.chess_state_history.get_next_state()?;
+        return Some(self.resume_clock(live_clock));
+    }
+
+    /**
+     * Undo against the AI takes back the AI's reply along with the move it answered,
+     * so it's the human's turn again and the AI doesn't reply until they move.
+     * None if the human hasn't made a move to take back
+     */
+    pub fn undo_past_ai_reply(&mut self, ai_player: Player) -> Option<ChessState> {
+        let history = &self.chess_state_history;
+        let mut index = history.current_state_index;
+        while index > 0 && history.state_history[index - 1].player == ai_player {
+            index -= 1;
+        }
+        if index == 0 {
+            return None;
+        }
+        let steps = history.current_state_index - (index - 1);
+        let mut chess_state = None;
+        for _ in 0..steps {
+            chess_state = self.get_previous_state();
+        }
+        return chess_state;
+    }
+
+    // the current clock with the running player charged for their turn so far
+    fn stop_clock(&self) -> Option<(ChessClock, u64)> {
+        let now_ms = ChessClock::now_ms();
+        let mut clock = self.get_current_state()?.clock?;
+        clock.stop(now_ms);
+        return Some((clock, now_ms));
+    }
+
+    // a state brought back by undo or redo carries on with the time the players have left
+    fn resume_clock(&mut self, live_clock: Option<(ChessClock, u64)>) -> ChessState {
+        let chess_state = self.chess_state_history.get_current_state_mut().unwrap();
+        if let (Some(clock), Some((live_clock, now_ms))) = (chess_state.clock.as_mut(), live_clock)
+        {
+            clock.resume_from(&live_clock, now_ms);
+        }
+        return chess_state.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::TimeControl;
+
+    #[test]
+    fn test_undo_doesnt_give_clock_time_back() {
+        let settings = Settings {
+            time_control: Some(TimeControl::new(60_000, 0, None)),
+            ..Settings::default()
+        };
+        let chess_state = ChessState::new_with_settings(settings);
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        let mut game = Game::new("test".to_string(), chess_state_history);
+        assert!(game.move_piece(1, 4, 3, 4));
+        assert!(game.move_piece(6, 4, 4, 4));
+        // white thinks for five seconds before Nf3
+        let white_remaining = |game: &Game| {
+            let clock = game.get_current_state().unwrap().clock.unwrap();
+            return clock.remaining_ms(Player::White, ChessClock::now_ms());
+        };
+        let chess_state = game.chess_state_history.get_current_state_mut().unwrap();
+        chess_state.clock.as_mut().unwrap().turn_started_at_ms -= 5000;
+        assert!(game.move_piece(0, 6, 2, 5));
+        assert!(white_remaining(&game) <= 55_000);
+
+        // taking Nf3 back and playing it again still leaves white five seconds down
+        assert!(game.get_previous_state().is_some());
+        assert!(white_remaining(&game) <= 55_000);
+        assert!(game.move_piece(0, 6, 2, 5));
+        assert!(white_remaining(&game) <= 55_000);
+        // and so does redoing it
+        assert!(game.get_previous_state().is_some());
+        assert!(game.get_next_state().is_some());
+        assert!(white_remaining(&game) <= 55_000);
     }
 }
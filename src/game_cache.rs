@@ -0,0 +1,388 @@
+// Keeps the games being played in memory in front of the configured GameStore.
+//
+// Loads are served from memory once a game has been read, saves are taken in memory and
+// written to the store in the background. Only the latest version of a game is written,
+// the store's version check lets the ones in between be skipped. Games nobody has touched
+// for a while are written out if needed and dropped. A write the store fails is tried again
+// later and later. A game the store turns down as stale is kept, so the next save of it is
+// turned down for the players to see, and the one after that is read back fresh.
+
+use crate::database::{check_version, GameStore, SaveKind, StoreError, VersionConflict};
+use crate::game::Game;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// how often held back saves are written to the store
+pub const WRITE_BEHIND_INTERVAL: Duration = Duration::from_secs(1);
+// a game that isn't loaded or saved for this long is dropped from memory
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+// the longest a failed write waits before it's tried again
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct CachedGame {
+    game: Game,
+    // saved in memory but not yet written to the store
+    dirty: bool,
+    last_used: Instant,
+    // writes to the store that failed in a row, and when the next one is due
+    failed_writes: u32,
+    retry_at: Option<Instant>,
+    // the store has a newer save from somewhere else, this copy can never be written
+    stale: bool,
+}
+
+impl CachedGame {
+    fn new(game: Game) -> Self {
+        CachedGame {
+            game,
+            dirty: false,
+            last_used: Instant::now(),
+            failed_writes: 0,
+            retry_at: None,
+            stale: false,
+        }
+    }
+
+    fn write_due(&self, now: Instant) -> bool {
+        return self.dirty && self.retry_at.is_none_or(|retry_at| retry_at <= now);
+    }
+}
+
+pub struct GameCache {
+    games: Arc<Mutex<HashMap<String, CachedGame>>>,
+    store: Arc<dyn GameStore>,
+    idle_timeout: Duration,
+    // one flush at a time, so the background writes and the one on shutdown don't overlap
+    flushing: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl GameCache {
+    pub fn new(store: Box<dyn GameStore>, idle_timeout: Duration) -> Self {
+        GameCache {
+            games: Arc::new(Mutex::new(HashMap::new())),
+            store: Arc::from(store),
+            idle_timeout,
+            flushing: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /**
+     * Writes the held back saves and drops idle games every interval, for as long as the
+     * server runs. Has to be started from inside the tokio runtime
+     */
+    pub fn spawn_write_behind(&self, interval: Duration) {
+        let cache = GameCache {
+            games: self.games.clone(),
+            store: self.store.clone(),
+            idle_timeout: self.idle_timeout,
+            flushing: self.flushing.clone(),
+        };
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(err) = cache.write_due(Instant::now()).await {
+                    println!("Failed to write games: {:?}", err);
+                }
+                cache.evict_idle(Instant::now());
+            }
+        });
+    }
+
+    // drops the games that haven't been used since the idle timeout, unless they still need writing
+    pub fn evict_idle(&self, now: Instant) -> usize {
+        let mut games = self.games.lock().unwrap();
+        let before = games.len();
+        games.retain(|_, cached| {
+            cached.dirty || now.duration_since(cached.last_used) < self.idle_timeout
+        });
+        return before - games.len();
+    }
+
+    pub fn cached_games(&self) -> usize {
+        return self.games.lock().unwrap().len();
+    }
+
+    // writes the saves held back, leaving the ones still waiting to retry a failed write
+    pub async fn write_due(&self, now: Instant) -> Result<(), StoreError> {
+        return self.write_dirty(Some(now)).await;
+    }
+
+    async fn write_dirty(&self, now: Option<Instant>) -> Result<(), StoreError> {
+        let _flushing = self.flushing.lock().await;
+        let dirty: Vec<Game> = self
+            .games
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|cached| now.map_or(cached.dirty, |now| cached.write_due(now)))
+            .map(|cached| cached.game.clone())
+            .collect();
+        let mut result = Ok(());
+        for game in dirty {
+            let written = self.store.save(&game, SaveKind::Update).await;
+            let mut games = self.games.lock().unwrap();
+            // a save that came in while this one was written is left for next time
+            let unchanged = games
+                .get(&game.name)
+                .is_some_and(|cached| cached.game.version == game.version);
+            match written {
+                Ok(()) => {
+                    if let Some(cached) = games.get_mut(&game.name) {
+                        cached.dirty = !unchanged;
+                        cached.failed_writes = 0;
+                        cached.retry_at = None;
+                    }
+                }
+                // kept until the next save through the cache reports it
+                Err(err) if err.is::<VersionConflict>() => {
+                    if let Some(cached) = games.get_mut(&game.name).filter(|_| unchanged) {
+                        cached.dirty = false;
+                        cached.stale = true;
+                    }
+                    result = Err(err);
+                }
+                Err(err) => {
+                    let failed_writes = games.get_mut(&game.name).map_or(1, |cached| {
+                        cached.failed_writes += 1;
+                        let backoff = WRITE_BEHIND_INTERVAL
+                            .saturating_mul(1 << cached.failed_writes.min(16))
+                            .min(MAX_RETRY_INTERVAL);
+                        cached.retry_at = Some(Instant::now() + backoff);
+                        cached.failed_writes
+                    });
+                    result = Err(format!(
+                        "Failed to write {} ({} in a row): {}",
+                        game.name, failed_writes, err
+                    )
+                    .into());
+                }
+            }
+        }
+        return result;
+    }
+}
+
+#[async_trait]
+impl GameStore for GameCache {
+    async fn save(&self, game: &Game, kind: SaveKind) -> Result<(), StoreError> {
+        {
+            let mut games = self.games.lock().unwrap();
+            if let Some(cached) = games.get_mut(&game.name) {
+                // the moves since the store's save are lost, the game is read back from it
+                if cached.stale {
+                    games.remove(&game.name);
+                    return Err(VersionConflict(format!(
+                        "{} was saved somewhere else, the moves since then couldn't be kept",
+                        game.name
+                    ))
+                    .into());
+                }
+                check_version(&game.name, Some(cached.game.version), game.version, kind)?;
+                cached.game = game.clone();
+                cached.dirty = true;
+                cached.last_used = Instant::now();
+                return Ok(());
+            }
+        }
+        // not in memory, the store has the version to check against
+        self.store.save(game, kind).await?;
+        let cached = CachedGame::new(game.clone());
+        self.games.lock().unwrap().insert(game.name.clone(), cached);
+        Ok(())
+    }
+
+    async fn load(&self, name: &str) -> Result<Game, StoreError> {
+        if let Some(cached) = self.games.lock().unwrap().get_mut(name) {
+            cached.last_used = Instant::now();
+            return Ok(cached.game.clone());
+        }
+        let game = self.store.load(name).await?;
+        let mut games = self.games.lock().unwrap();
+        // a save that came in while the store was being read is newer
+        let cached = games
+            .entry(name.to_string())
+            .or_insert(CachedGame::new(game));
+        return Ok(cached.game.clone());
+    }
+
+    // writes every game saved since the last flush, even the ones waiting to retry
+    async fn flush(&self) -> Result<(), StoreError> {
+        return self.write_dirty(None).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_state_history::ChessStateHistory;
+    use crate::chess_structs::ChessState;
+    use crate::database::MemoryStore;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn game(name: &str) -> Game {
+        let chess_state = ChessState::new();
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        let mut game = Game::new(name.to_string(), chess_state_history);
+        game.version = 1;
+        return game;
+    }
+
+    // a memory store whose saves can be made to fail, the way a full disk would
+    struct FailingStore {
+        store: MemoryStore,
+        failing: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl GameStore for FailingStore {
+        async fn save(&self, game: &Game, kind: SaveKind) -> Result<(), StoreError> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err("The disk is full".into());
+            }
+            return self.store.save(game, kind).await;
+        }
+
+        async fn load(&self, name: &str) -> Result<Game, StoreError> {
+            return self.store.load(name).await;
+        }
+    }
+
+    // a cache over a memory store, with the store kept to look behind the cache
+    fn cache() -> (GameCache, Arc<dyn GameStore>) {
+        let cache = GameCache::new(Box::new(MemoryStore::new()), IDLE_TIMEOUT);
+        let store = cache.store.clone();
+        return (cache, store);
+    }
+
+    #[tokio::test]
+    async fn test_saves_are_written_on_flush() {
+        let (cache, store) = cache();
+        let mut saved = game("cache_test");
+        cache.save(&saved, SaveKind::NewGame).await.unwrap();
+        // a game the cache hasn't seen is written straight away
+        assert_eq!(store.load("cache_test").await.unwrap(), saved);
+
+        let first_version = saved.clone();
+        saved.move_piece(1, 4, 3, 4);
+        saved.version += 1;
+        cache.save(&saved, SaveKind::Update).await.unwrap();
+        assert_eq!(cache.load("cache_test").await.unwrap(), saved);
+        assert_eq!(store.load("cache_test").await.unwrap(), first_version);
+
+        cache.flush().await.unwrap();
+        assert_eq!(store.load("cache_test").await.unwrap(), saved);
+    }
+
+    #[tokio::test]
+    async fn test_stale_save_is_rejected() {
+        let (cache, _) = cache();
+        let saved = game("cache_test");
+        cache.save(&saved, SaveKind::NewGame).await.unwrap();
+
+        let mut first = saved.clone();
+        let mut second = saved;
+        first.move_piece(1, 4, 3, 4);
+        first.version += 1;
+        second.move_piece(1, 3, 3, 3);
+        second.version += 1;
+        cache.save(&first, SaveKind::Update).await.unwrap();
+        assert!(cache.save(&second, SaveKind::Update).await.is_err());
+        assert_eq!(cache.load("cache_test").await.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn test_loads_come_from_the_store_once() {
+        let (cache, store) = cache();
+        let saved = game("cache_test");
+        store.save(&saved, SaveKind::NewGame).await.unwrap();
+        assert_eq!(cache.load("cache_test").await.unwrap(), saved);
+        assert_eq!(cache.cached_games(), 1);
+        assert!(cache.load("missing").await.is_err());
+        assert_eq!(cache.cached_games(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_idle_games_are_evicted() {
+        let (cache, store) = cache();
+        let mut saved = game("cache_test");
+        cache.save(&saved, SaveKind::NewGame).await.unwrap();
+        saved.version += 1;
+        cache.save(&saved, SaveKind::Update).await.unwrap();
+
+        // not written yet, so it stays however long it has been idle
+        let later = Instant::now() + IDLE_TIMEOUT * 2;
+        assert_eq!(cache.evict_idle(later), 0);
+        cache.flush().await.unwrap();
+        assert_eq!(cache.evict_idle(Instant::now()), 0);
+        assert_eq!(cache.evict_idle(later), 1);
+        assert_eq!(cache.cached_games(), 0);
+
+        // read back from the store after being dropped
+        assert_eq!(store.load("cache_test").await.unwrap(), saved);
+        assert_eq!(cache.load("cache_test").await.unwrap(), saved);
+    }
+
+    #[tokio::test]
+    async fn test_stale_write_is_reported() {
+        let (cache, store) = cache();
+        let mut saved = game("cache_test");
+        cache.save(&saved, SaveKind::NewGame).await.unwrap();
+
+        // another server saves the game behind the cache's back
+        let mut elsewhere = saved.clone();
+        elsewhere.move_piece(1, 3, 3, 3);
+        elsewhere.version += 1;
+        store.save(&elsewhere, SaveKind::Update).await.unwrap();
+
+        saved.move_piece(1, 4, 3, 4);
+        saved.version += 1;
+        cache.save(&saved, SaveKind::Update).await.unwrap();
+        assert!(cache.flush().await.unwrap_err().is::<VersionConflict>());
+        // nothing left that would fail every flush from now on
+        cache.flush().await.unwrap();
+        // the room still has the moves it was sent until the next save
+        assert_eq!(cache.load("cache_test").await.unwrap(), saved);
+
+        // which is turned down, so the players find out their moves were lost
+        saved.move_piece(6, 4, 4, 4);
+        saved.version += 1;
+        let error = cache.save(&saved, SaveKind::Update).await.unwrap_err();
+        assert!(error.is::<VersionConflict>());
+        assert_eq!(cache.load("cache_test").await.unwrap(), elsewhere);
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_back_off() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let store = FailingStore {
+            store: MemoryStore::new(),
+            failing: failing.clone(),
+        };
+        let cache = GameCache::new(Box::new(store), IDLE_TIMEOUT);
+        let mut saved = game("cache_test");
+        cache.save(&saved, SaveKind::NewGame).await.unwrap();
+        saved.version += 1;
+        cache.save(&saved, SaveKind::Update).await.unwrap();
+
+        failing.store(true, Ordering::SeqCst);
+        let error = cache.write_due(Instant::now()).await.unwrap_err();
+        assert!(error.to_string().contains("(1 in a row)"));
+        let error = cache.flush().await.unwrap_err();
+        assert!(error.to_string().contains("(2 in a row)"));
+
+        // not tried again until the back off is over
+        failing.store(false, Ordering::SeqCst);
+        cache.write_due(Instant::now()).await.unwrap();
+        assert_eq!(cache.store.load("cache_test").await.unwrap().version, 1);
+        cache
+            .write_due(Instant::now() + MAX_RETRY_INTERVAL)
+            .await
+            .unwrap();
+        assert_eq!(cache.store.load("cache_test").await.unwrap(), saved);
+        let games = cache.games.lock().unwrap();
+        assert!(!games["cache_test"].dirty);
+        assert_eq!(games["cache_test"].failed_writes, 0);
+    }
+}
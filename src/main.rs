@@ -1,6 +1,6 @@
 use axum::{
     extract::{Json, Query},
-    routing::get,
+    routing::{get, post},
     Router,
     Server,
 };
@@ -15,33 +15,46 @@ use serde::{Deserialize, Serialize};
  */
 
 pub mod websockets;
+pub mod ai;
 pub mod chess;
+pub mod chess_clock;
 pub mod chess_history;
 pub mod chess_state;
 pub mod chess_state_history;
 pub mod chess_structs;
 pub mod database;
+pub mod fen;
 pub mod game;
+pub mod game_cache;
 pub mod messages;
+pub mod move_rolls;
 pub mod moves;
 pub mod name_generator;
+pub mod notation;
+pub mod perft;
+pub mod pgn;
 pub mod pieces;
 pub mod pokemon_names;
 pub mod pokemon_types;
+pub mod save_format;
+pub mod save_migrations;
 pub mod settings;
+pub mod type_assignment;
 pub mod app_state;
 
+use crate::ai::{Hint, DEFAULT_HINT_COUNT};
 use crate::chess_state_history::ChessStateHistory;
-use crate::chess_structs::{ChessState, Move, Winner};
+use crate::chess_structs::{ChessState, Move, Player, PokemonType, Winner};
 use crate::game::Game;
+use crate::game_cache::{GameCache, IDLE_TIMEOUT, WRITE_BEHIND_INTERVAL};
 use crate::name_generator::generate_game_name;
-use crate::settings::Settings;
+use crate::settings::{Difficulty, Settings, TimeControl, TypeAssignment};
 use tower_http::cors::{CorsLayer, Any};
 use crate::websockets::handler;
 use crate::app_state::AppState;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use axum::http::Method;
+use axum::http::{Method, StatusCode};
 
 
 use axum::Extension;
@@ -52,11 +65,17 @@ async fn main() {
     env_logger::init();
     println!("Starting server...");
 
+    // games go to the games/ directory unless GAME_STORE says otherwise,
+    // the games being played are kept in memory in front of it
+    let game_cache = GameCache::new(database::game_store_from_env().unwrap(), IDLE_TIMEOUT);
+    game_cache.spawn_write_behind(WRITE_BEHIND_INTERVAL);
+    database::configure_game_store(Box::new(game_cache)).unwrap();
+
     let app_state = Arc::new(Mutex::new(AppState::new()));
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
-        .allow_methods(vec![Method::GET, Method::OPTIONS])
+        .allow_methods(vec![Method::GET, Method::POST, Method::OPTIONS])
         .allow_headers(Any);
 
     let app = Router::new()
@@ -64,6 +83,9 @@ async fn main() {
         .route("/start", get(start_game))
         .route("/generate_name", get(get_game_name))
         .route("/get_game_state", get(get_game_state))
+        .route("/get_hint", get(get_hint))
+        .route("/export", get(export_game))
+        .route("/import", post(import_game))
         .route("/ws", get(handler))
         .layer(Extension(app_state))
         .layer(cors);
@@ -71,8 +93,35 @@ async fn main() {
     println!("Server starting on 0.0.0.0:3000");
     Server::bind(&"0.0.0.0:3000".parse().unwrap())
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    // saves the cache is still holding on to
+    if let Err(err) = database::game_store().flush().await {
+        println!("Failed to write games on shutdown: {:?}", err);
+    }
+    println!("Server stopped");
+}
+
+// ctrl-c, or a terminate from whatever is running the server
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.unwrap();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
 
 #[derive(Deserialize)]
@@ -81,6 +130,20 @@ pub struct StartGame {
     pub local_play: bool,
     pub critical_hits: bool,
     pub misses: bool,
+    // time control, the game has no clocks without a base time
+    pub base_seconds: Option<u64>,
+    pub increment_seconds: Option<u64>,
+    pub delay_seconds: Option<u64>,
+    // the side the computer plays, if any
+    pub ai_player: Option<Player>,
+    pub ai_difficulty: Option<Difficulty>,
+    // a FEN position with pokemon types to start from, see fen.rs
+    pub position: Option<String>,
+    // the same seed deals the same types, to rematch on the same layout
+    pub seed: Option<u64>,
+    // how the pieces get their types, see TypeAssignment, and the type for AllOneType
+    pub type_assignment: Option<String>,
+    pub one_type: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -88,25 +151,128 @@ pub struct GetGame {
     pub name: String,
 }
 
-async fn start_game(Query(params): Query<StartGame>) -> Json<ChessState> {
-    let settings = Settings::new(params.local_play, params.critical_hits, params.misses);
-    let chess_state = ChessState::new();
-    let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state.clone());
+async fn start_game(
+    Query(params): Query<StartGame>,
+) -> Result<Json<ChessState>, (StatusCode, String)> {
+    if Game::exists(&params.name).await {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("There's already a game called {}", params.name),
+        ));
+    }
+    let mut settings = Settings::new(params.local_play, params.critical_hits, params.misses);
+    settings.time_control = params.base_seconds.map(|base_seconds| {
+        TimeControl::new(
+            base_seconds * 1000,
+            params.increment_seconds.unwrap_or(0) * 1000,
+            params.delay_seconds.map(|delay_seconds| delay_seconds * 1000),
+        )
+    });
+    settings.ai_player = params.ai_player;
+    settings.ai_difficulty = params.ai_difficulty.unwrap_or_default();
+    // a game always keeps its seed, so any layout can be dealt again
+    settings.seed = Some(params.seed.unwrap_or_else(rand::random));
+    if let Some(type_assignment) = &params.type_assignment {
+        let one_type = params.one_type.as_deref().and_then(PokemonType::from_name);
+        settings.type_assignment = TypeAssignment::from_name(type_assignment, one_type)
+            .map_err(|error| (StatusCode::BAD_REQUEST, error))?;
+    }
+    let chess_state = match &params.position {
+        Some(position) => ChessState::from_fen(position, settings)
+            .map_err(|error| (StatusCode::BAD_REQUEST, error))?,
+        None => ChessState::new_with_settings(settings),
+    };
+    let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
     let name = params.name.clone();
-    let game = Game::new(name, settings, chess_state_history);
+    let mut game = Game::new(name, chess_state_history);
+    // an AI playing white makes the first move before anyone joins
+    let mut game = tokio::task::spawn_blocking(move || {
+        game.play_ai_turns();
+        game
+    })
+    .await
+    .unwrap();
 
     // Save the board
-    if game.save().await {
-        info!("Saved board");
-    }
+    game.save_new()
+        .await
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+    info!("Saved board");
 
-    Json(chess_state)
+    Ok(Json(game.get_current_state().unwrap().for_players()))
 }
 
 async fn get_game_state(Query(params): Query<GetGame>) -> Json<Option<ChessState>> {
     let game = Game::load(&params.name).await;
     let chess_state = game.get_current_state();
-    return Json(chess_state);
+    return Json(chess_state.map(|chess_state| chess_state.for_players()));
+}
+
+fn no_game_called(name: &str) -> (StatusCode, String) {
+    return (
+        StatusCode::NOT_FOUND,
+        format!("There's no game called {}", name),
+    );
+}
+
+// the game's moves as PGN, for sharing and archiving finished games
+async fn export_game(Query(params): Query<GetGame>) -> Result<String, (StatusCode, String)> {
+    if !Game::exists(&params.name).await {
+        return Err(no_game_called(&params.name));
+    }
+    let game = Game::load(&params.name).await;
+    return Ok(game.to_pgn());
+}
+
+#[derive(Deserialize)]
+pub struct ImportGame {
+    pub name: String,
+    pub local_play: bool,
+    // the game as exported from /export, moves along with the starting types
+    pub pgn: String,
+}
+
+// replays an exported game move by move and saves it under a new name
+async fn import_game(
+    Json(params): Json<ImportGame>,
+) -> Result<Json<ChessState>, (StatusCode, String)> {
+    if Game::exists(&params.name).await {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("There's already a game called {}", params.name),
+        ));
+    }
+    let mut game = Game::from_pgn(params.name, &params.pgn, params.local_play)
+        .map_err(|error| (StatusCode::BAD_REQUEST, error))?;
+    game.save_new()
+        .await
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+    info!("Saved imported game");
+    Ok(Json(game.get_current_state().unwrap().for_players()))
+}
+
+#[derive(Deserialize)]
+pub struct GetHint {
+    pub name: String,
+    pub count: Option<usize>,
+}
+
+async fn get_hint(Query(params): Query<GetHint>) -> Result<Json<Vec<Hint>>, (StatusCode, String)> {
+    if !Game::exists(&params.name).await {
+        return Err(no_game_called(&params.name));
+    }
+    let game = Game::load(&params.name).await;
+    let count = params.count.unwrap_or(DEFAULT_HINT_COUNT);
+    // a search that panics is an error for this request, not a dropped connection
+    let hints = tokio::task::spawn_blocking(move || game.get_hints(count))
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to analyse the game".to_string(),
+            )
+        })?;
+    return Ok(Json(hints));
 }
 
 // The query parameters for todos index
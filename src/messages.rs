@@ -1,4 +1,4 @@
-use crate::chess_structs::{InfoMessage, InteractionType};
+use crate::chess_structs::{InfoMessage, InteractionType, Move};
 
 impl InfoMessage {
     pub fn get_message_from_interaction_type(
@@ -15,4 +15,19 @@ impl InfoMessage {
             _ => None,
         }
     }
+
+    pub fn get_message_from_move(
+        last_move: Option<Move>,
+        moves_available: bool,
+    ) -> Option<InfoMessage> {
+        match last_move {
+            Some(m) if m.missed => Some(InfoMessage::Missed),
+            Some(m) if m.critical_hit => Some(InfoMessage::CriticalHit),
+            Some(m) => InfoMessage::get_message_from_interaction_type(
+                m.type_interaction.unwrap_or(InteractionType::Normal),
+                moves_available,
+            ),
+            None => None,
+        }
+    }
 }
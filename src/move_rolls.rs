@@ -0,0 +1,35 @@
+use crate::chess_structs::MoveRolls;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Critical hit odds match the main series games
+pub const CRITICAL_HIT_CHANCE: f64 = 1.0 / 16.0;
+pub const MISS_CHANCE: f64 = 1.0 / 10.0;
+
+impl MoveRolls {
+    pub fn none() -> Self {
+        MoveRolls {
+            critical_hit: false,
+            miss: false,
+        }
+    }
+
+    /**
+     * Rolls are derived from the game's seed and the turn number instead of thread_rng,
+     * so undoing and replaying a move, or reloading the game from disk,
+     * always gives the same outcome
+     */
+    pub fn roll(rng_seed: u64, turn_count: usize) -> Self {
+        let mut rng = MoveRolls::rng_for_turn(rng_seed, turn_count);
+        // always roll both so turning one setting on doesn't change the other's outcomes
+        let critical_hit = rng.gen_bool(CRITICAL_HIT_CHANCE);
+        let miss = rng.gen_bool(MISS_CHANCE);
+        return MoveRolls { critical_hit, miss };
+    }
+
+    fn rng_for_turn(rng_seed: u64, turn_count: usize) -> StdRng {
+        // spread the turn count over the whole seed so neighbouring turns don't share rolls
+        let turn_mix = (turn_count as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        return StdRng::seed_from_u64(rng_seed ^ turn_mix);
+    }
+}
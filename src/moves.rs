@@ -1,4 +1,4 @@
-use crate::chess_structs::{Capture, ChessPieceType, Move, Piece, PokemonType};
+use crate::chess_structs::{Capture, ChessPieceType, InteractionType, Move, Piece, PokemonType};
 
 impl Move {
     // Create a new en passant move. This function assumes that the move is legal
@@ -32,6 +32,28 @@ impl Move {
                 },
             }),
             castle: None,
+            critical_hit: false,
+            missed: false,
         }
     }
+
+    /**
+     * A capture or a pawn move can never be undone, so no earlier position can repeat after it.
+     * A miss or a no effect bounce leaves the board as it was
+     */
+    pub fn is_irreversible(&self) -> bool {
+        if self.missed {
+            return false;
+        }
+        let bounced =
+            self.type_interaction == Some(InteractionType::NoEffect) && !self.critical_hit;
+        if bounced {
+            return false;
+        }
+        let is_pawn_move = matches!(
+            self.piece_type,
+            ChessPieceType::WhitePawn | ChessPieceType::BlackPawn
+        );
+        return self.capture.is_some() || is_pawn_move;
+    }
 }
@@ -0,0 +1,352 @@
+use crate::chess_structs::{ChessPieceType, ChessState, Move, BOARD_SIZE};
+use crate::fen::{parse_square, square_name};
+
+impl Move {
+    /**
+     * Standard algebraic notation for a move from the given position, Nbxe5 or O-O.
+     * A promotion and check or mate depend on what happens after the move,
+     * so they are left to the caller
+     */
+    pub fn to_san(&self, chess_state: &ChessState) -> String {
+        if let Some(castle) = self.castle {
+            return if castle.rook_from_col == 0 {
+                "O-O-O".to_string()
+            } else {
+                "O-O".to_string()
+            };
+        }
+        let mut san = String::new();
+        let is_pawn = self.is_pawn_move();
+        if is_pawn {
+            if self.capture.is_some() {
+                san.push(file_letter(self.from_col));
+            }
+        } else {
+            san.push(self.piece_type.fen_char().to_ascii_uppercase());
+            san.push_str(&self.disambiguation(chess_state));
+        }
+        if self.capture.is_some() {
+            san.push('x');
+        }
+        san.push_str(&square_name(self.to_row, self.to_col));
+        return san;
+    }
+
+    /**
+     * The same SAN with the promotion on the end, b8=Q
+     */
+    pub fn to_san_with_promotion(&self, chess_state: &ChessState, promotion: &str) -> String {
+        let mut san = self.to_san(chess_state);
+        if let Some(letter) = promotion_letter(promotion) {
+            san.push('=');
+            san.push(letter);
+        }
+        return san;
+    }
+
+    /**
+     * The from and to squares, with the promotion piece in lowercase if there is one, e7e8q.
+     * Castling is the king's move, e1g1
+     */
+    pub fn to_uci(&self, promotion: Option<&str>) -> String {
+        let mut uci = square_name(self.from_row, self.from_col);
+        uci.push_str(&square_name(self.to_row, self.to_col));
+        if let Some(letter) = promotion.and_then(promotion_letter) {
+            uci.push(letter.to_ascii_lowercase());
+        }
+        return uci;
+    }
+
+    /**
+     * Finds the legal move for either SAN or UCI, along with the piece a promotion asks for
+     */
+    pub fn from_notation(
+        notation: &str,
+        chess_state: &ChessState,
+    ) -> Result<(Move, Option<String>), String> {
+        let notation = notation.trim();
+        if looks_like_uci(notation) {
+            return Move::from_uci(notation, chess_state);
+        }
+        return Move::from_san(notation, chess_state);
+    }
+
+    /**
+     * Finds the legal move a UCI string stands for, e2e4 or e7e8q
+     */
+    pub fn from_uci(uci: &str, chess_state: &ChessState) -> Result<(Move, Option<String>), String> {
+        if !looks_like_uci(uci) {
+            return Err(format!("{} isn't a valid move", uci));
+        }
+        let (from_row, from_col) = parse_square(&uci[0..2]).unwrap();
+        let (to_row, to_col) = parse_square(&uci[2..4]).unwrap();
+        let promotion = uci[4..].chars().next().and_then(promotion_piece_name);
+        let m = chess_state
+            .get_valid_moves(from_row, from_col)
+            .into_iter()
+            .find(|m| (m.to_row, m.to_col) == (to_row, to_col))
+            .ok_or(format!("{} isn't a legal move", uci))?;
+        return Ok((m, promotion.map(str::to_string)));
+    }
+
+    /**
+     * Finds the legal move a SAN string stands for, along with the piece a promotion
+     * asks for. Check and mate markers are ignored, the position decides those.
+     * A missing x, 0-0 for O-O, more disambiguation than needed and a promotion
+     * without the = are all taken
+     */
+    pub fn from_san(san: &str, chess_state: &ChessState) -> Result<(Move, Option<String>), String> {
+        let invalid = || format!("{} isn't a valid move", san);
+        let mut rest = san.trim().trim_end_matches(['+', '#', '!', '?']);
+
+        let castle_col = match rest {
+            "O-O" | "0-0" => Some(BOARD_SIZE - 1),
+            "O-O-O" | "0-0-0" => Some(0),
+            _ => None,
+        };
+        if let Some(rook_from_col) = castle_col {
+            let m = chess_state
+                .all_legal_moves()
+                .into_iter()
+                .find(|m| m.castle.map(|castle| castle.rook_from_col) == Some(rook_from_col))
+                .ok_or(format!("{} isn't a legal move", san))?;
+            return Ok((m, None));
+        }
+
+        let mut promotion = None;
+        if let Some(last) = rest.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+            let name = promotion_piece_name(last).ok_or_else(invalid)?;
+            promotion = Some(name.to_string());
+            rest = rest[..rest.len() - 1].trim_end_matches('=');
+        }
+
+        let piece_letter = rest
+            .chars()
+            .next()
+            .filter(|c| "KQRBN".contains(*c))
+            .unwrap_or('P');
+        if piece_letter != 'P' {
+            rest = &rest[1..];
+        }
+        if rest.len() < 2 || !rest.is_char_boundary(rest.len() - 2) {
+            return Err(invalid());
+        }
+        let (from, to) = rest.split_at(rest.len() - 2);
+        let (to_row, to_col) = parse_square(to).ok_or_else(invalid)?;
+        let mut from_col = None;
+        let mut from_row = None;
+        for c in from.chars().filter(|c| *c != 'x' && *c != ':' && *c != '-') {
+            match c {
+                'a'..='h' => from_col = Some(c as usize - 'a' as usize),
+                '1'..='8' => from_row = Some(c as usize - '1' as usize),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let candidates: Vec<Move> = chess_state
+            .all_legal_moves()
+            .into_iter()
+            .filter(|m| {
+                m.piece_type.fen_char().to_ascii_uppercase() == piece_letter
+                    && (m.to_row, m.to_col) == (to_row, to_col)
+                    && from_col.is_none_or(|col| col == m.from_col)
+                    && from_row.is_none_or(|row| row == m.from_row)
+            })
+            .collect();
+        return match candidates.as_slice() {
+            [m] => Ok((*m, promotion)),
+            [] => Err(format!("{} isn't a legal move", san)),
+            _ => Err(format!("{} could be more than one move", san)),
+        };
+    }
+
+    pub fn is_pawn_move(&self) -> bool {
+        return matches!(
+            self.piece_type,
+            ChessPieceType::WhitePawn | ChessPieceType::BlackPawn
+        );
+    }
+
+    // a pawn reaching the last rank, whether it survives to be promoted is up to the capture
+    pub fn is_promotion(&self) -> bool {
+        return self.is_pawn_move() && (self.to_row == 0 || self.to_row == BOARD_SIZE - 1);
+    }
+
+    pub fn is_en_passant(&self) -> bool {
+        return match self.capture {
+            Some(capture) => {
+                self.is_pawn_move() && (capture.row, capture.col) != (self.to_row, self.to_col)
+            }
+            None => false,
+        };
+    }
+
+    // the file, the rank or both, if another piece of the same kind could move to the same square
+    fn disambiguation(&self, chess_state: &ChessState) -> String {
+        let rivals: Vec<Move> = chess_state
+            .all_legal_moves()
+            .into_iter()
+            .filter(|m| {
+                m.piece_type == self.piece_type
+                    && (m.to_row, m.to_col) == (self.to_row, self.to_col)
+                    && (m.from_row, m.from_col) != (self.from_row, self.from_col)
+            })
+            .collect();
+        if rivals.is_empty() {
+            return String::new();
+        }
+        if rivals.iter().all(|m| m.from_col != self.from_col) {
+            return file_letter(self.from_col).to_string();
+        }
+        if rivals.iter().all(|m| m.from_row != self.from_row) {
+            return (self.from_row + 1).to_string();
+        }
+        return square_name(self.from_row, self.from_col);
+    }
+}
+
+// the name select_pawn_promotion_piece takes, from the letter SAN uses
+pub fn promotion_piece_name(letter: char) -> Option<&'static str> {
+    return match letter.to_ascii_uppercase() {
+        'Q' => Some("Queen"),
+        'R' => Some("Rook"),
+        'B' => Some("Bishop"),
+        'N' => Some("Knight"),
+        _ => None,
+    };
+}
+
+// the letter SAN uses for a promotion piece, from the name select_pawn_promotion_piece takes
+pub fn promotion_letter(piece_name: &str) -> Option<char> {
+    return match piece_name {
+        "Queen" => Some('Q'),
+        "Rook" => Some('R'),
+        "Bishop" => Some('B'),
+        "Knight" => Some('N'),
+        _ => None,
+    };
+}
+
+// two squares and maybe a promotion letter, e2e4 or e7e8q
+fn looks_like_uci(notation: &str) -> bool {
+    let bytes = notation.as_bytes();
+    let is_square =
+        |file: u8, rank: u8| (b'a'..=b'h').contains(&file) && (b'1'..=b'8').contains(&rank);
+    return match bytes.len() {
+        4 | 5 => {
+            is_square(bytes[0], bytes[1])
+                && is_square(bytes[2], bytes[3])
+                && (bytes.len() == 4 || b"qrbn".contains(&bytes[4]))
+        }
+        _ => false,
+    };
+}
+
+fn file_letter(col: usize) -> char {
+    return (b'a' + col as u8) as char;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    fn san_for(fen: &str, from: (usize, usize), to: (usize, usize)) -> String {
+        let chess_state = ChessState::from_fen(fen, Settings::default()).unwrap();
+        let m = chess_state
+            .get_valid_moves(from.0, from.1)
+            .into_iter()
+            .find(|m| (m.to_row, m.to_col) == to)
+            .unwrap();
+        return m.to_san(&chess_state);
+    }
+
+    #[test]
+    fn test_san() {
+        let start = crate::fen::START_POSITION;
+        assert_eq!(san_for(start, (1, 4), (3, 4)), "e4");
+        assert_eq!(san_for(start, (0, 6), (2, 5)), "Nf3");
+        // pawn captures and castling
+        let fen = "r3k3/8/8/3p4/4P3/8/8/4K2R w K - 0 1";
+        assert_eq!(san_for(fen, (3, 4), (4, 3)), "exd5");
+        assert_eq!(san_for(fen, (0, 4), (0, 6)), "O-O");
+        assert_eq!(san_for(fen, (0, 7), (7, 7)), "Rh8");
+    }
+
+    #[test]
+    fn test_san_disambiguation() {
+        // knights on b1 and f1 both reach d2, rooks on a1 and a5 both reach a3
+        let fen = "4k3/8/8/R7/8/8/8/RN2KN2 w - - 0 1";
+        assert_eq!(san_for(fen, (0, 1), (1, 3)), "Nbd2");
+        assert_eq!(san_for(fen, (0, 0), (2, 0)), "R1a3");
+        assert_eq!(san_for(fen, (4, 0), (2, 0)), "R5a3");
+    }
+
+    #[test]
+    fn test_from_san() {
+        let chess_state = ChessState::new();
+        let (m, promotion) = Move::from_san("Nf3", &chess_state).unwrap();
+        assert_eq!((m.from_row, m.from_col, m.to_row, m.to_col), (0, 6, 2, 5));
+        assert_eq!(promotion, None);
+        assert!(Move::from_san("Nf4", &chess_state).is_err());
+        assert!(Move::from_san("Zf3", &chess_state).is_err());
+        assert!(Move::from_san("", &chess_state).is_err());
+        let chess_state =
+            ChessState::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", Settings::default()).unwrap();
+        let (m, promotion) = Move::from_san("b8=N+", &chess_state).unwrap();
+        assert_eq!((m.to_row, m.to_col), (7, 1));
+        assert_eq!(promotion, Some("Knight".to_string()));
+        assert_eq!(
+            Move::from_san("b8Q", &chess_state).unwrap().1,
+            Some("Queen".to_string())
+        );
+        assert!(Move::from_san("b8=K", &chess_state).is_err());
+    }
+
+    #[test]
+    fn test_from_san_disambiguation_and_castling() {
+        let fen = "4k3/8/8/R7/8/8/8/RN2K2R w K - 0 1";
+        let chess_state = ChessState::from_fen(fen, Settings::default()).unwrap();
+        let from = |san: &str| {
+            let (m, _) = Move::from_san(san, &chess_state).unwrap();
+            return (m.from_row, m.from_col);
+        };
+        assert_eq!(from("R1a3"), (0, 0));
+        assert_eq!(from("R5a3"), (4, 0));
+        // more than needed is fine, too little isn't
+        assert_eq!(from("Ra5a3"), (4, 0));
+        assert!(Move::from_san("Ra3", &chess_state).is_err());
+        let (m, _) = Move::from_san("O-O", &chess_state).unwrap();
+        assert_eq!((m.to_row, m.to_col), (0, 6));
+        assert_eq!(Move::from_san("0-0", &chess_state).unwrap().0, m);
+        assert!(Move::from_san("O-O-O", &chess_state).is_err());
+
+        // a capture without the x
+        let chess_state =
+            ChessState::from_fen("r3k3/8/8/3p4/4P3/8/8/4K2R w K - 0 1", Settings::default())
+                .unwrap();
+        let (m, _) = Move::from_san("ed5", &chess_state).unwrap();
+        assert!(m.capture.is_some());
+    }
+
+    #[test]
+    fn test_uci() {
+        let chess_state = ChessState::new();
+        let (m, promotion) = Move::from_notation("e2e4", &chess_state).unwrap();
+        assert_eq!((m.from_row, m.from_col, m.to_row, m.to_col), (1, 4, 3, 4));
+        assert_eq!(promotion, None);
+        assert_eq!(m.to_uci(None), "e2e4");
+        assert!(Move::from_uci("e2e5", &chess_state).is_err());
+        assert!(Move::from_uci("e2", &chess_state).is_err());
+
+        let chess_state =
+            ChessState::from_fen("4k3/1P6/8/8/8/8/8/4K2R w K - 0 1", Settings::default()).unwrap();
+        let (m, promotion) = Move::from_notation("b7b8r", &chess_state).unwrap();
+        assert_eq!(promotion, Some("Rook".to_string()));
+        assert_eq!(m.to_uci(promotion.as_deref()), "b7b8r");
+        assert_eq!(m.to_san_with_promotion(&chess_state, "Rook"), "b8=R");
+        let (m, _) = Move::from_notation("O-O", &chess_state).unwrap();
+        assert_eq!(m.to_uci(None), "e1g1");
+        assert_eq!(Move::from_notation("e1g1", &chess_state).unwrap().0, m);
+    }
+}
@@ -0,0 +1,247 @@
+// Perft walks every move path to a fixed depth and counts the positions at the end,
+// comparing the counts against known values catches move generation regressions
+
+use crate::chess_structs::{ChessState, Move, Winner, BOARD_SIZE};
+
+// a promoting pawn can become any of these, each one counts as its own move
+pub const PROMOTION_PIECES: [&str; 4] = ["Queen", "Rook", "Bishop", "Knight"];
+
+/**
+ * Counts the positions reachable in exactly `depth` plies. Candidate moves come from
+ * ChessBoard::possible_moves_for_piece and are played with ChessState::move_piece,
+ * which turns down the ones that leave the king in check.
+ * A super effective extra move is a ply of its own
+ */
+pub fn perft(chess_state: &ChessState, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let mut nodes = 0;
+    for next_state in next_states(chess_state) {
+        nodes += perft(&next_state, depth - 1);
+    }
+    return nodes;
+}
+
+/**
+ * The perft count below each legal move, for tracking down which move
+ * a wrong count comes from
+ */
+pub fn perft_divide(chess_state: &ChessState, depth: usize) -> Vec<(Move, u64)> {
+    let mut divided = Vec::new();
+    for m in candidate_moves(chess_state) {
+        let nodes: u64 = next_states_for_move(chess_state, &m)
+            .iter()
+            .map(|next_state| perft(next_state, depth.saturating_sub(1)))
+            .sum();
+        if nodes > 0 {
+            divided.push((m, nodes));
+        }
+    }
+    return divided;
+}
+
+fn next_states(chess_state: &ChessState) -> Vec<ChessState> {
+    let mut next_states = Vec::new();
+    for m in candidate_moves(chess_state) {
+        next_states.extend(next_states_for_move(chess_state, &m));
+    }
+    return next_states;
+}
+
+fn candidate_moves(chess_state: &ChessState) -> Vec<Move> {
+    let mut moves = Vec::new();
+    if chess_state.winner != Winner::NoneYet {
+        return moves;
+    }
+    for row in 0..BOARD_SIZE {
+        for col in 0..BOARD_SIZE {
+            moves.extend(chess_state.chessboard.possible_moves_for_piece(
+                row,
+                col,
+                chess_state.player,
+            ));
+        }
+    }
+    return moves;
+}
+
+// empty if the move is illegal, one state per promotion piece if it promotes
+fn next_states_for_move(chess_state: &ChessState, m: &Move) -> Vec<ChessState> {
+    let mut next_state = chess_state.clone();
+    if !next_state.move_piece(m.from_row, m.from_col, m.to_row, m.to_col) {
+        return vec![];
+    }
+    if !next_state.require_piece_selection {
+        return vec![next_state];
+    }
+    let mut promoted_states = Vec::new();
+    for piece in PROMOTION_PIECES {
+        let mut promoted_state = next_state.clone();
+        if promoted_state
+            .select_pawn_promotion_piece(piece.to_string())
+            .is_ok()
+        {
+            promoted_states.push(promoted_state);
+        }
+    }
+    return promoted_states;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_structs::{ChessBoard, ChessPieceType, Piece, Player, PokemonType};
+    use crate::settings::Settings;
+
+    /**
+     * Builds a position from its ranks, eighth rank first, in the letters FEN uses,
+     * with '.' for an empty square. Every piece is Normal type
+     */
+    fn position(ranks: [&str; BOARD_SIZE], player: Player) -> ChessState {
+        let mut chessboard = ChessBoard::new_normal_type_only();
+        for (rank_index, rank) in ranks.iter().enumerate() {
+            let row = BOARD_SIZE - 1 - rank_index;
+            for (col, letter) in rank.chars().enumerate() {
+                chessboard.board[row][col] = Piece {
+                    piece_type: ChessPieceType::from_fen_char(letter)
+                        .unwrap_or(ChessPieceType::Empty),
+                    pokemon_type: PokemonType::Normal,
+                };
+            }
+        }
+        let mut chess_state = ChessState::new_with_board(chessboard, Settings::default());
+        chess_state.player = player;
+        return chess_state;
+    }
+
+    /**
+     * Gives the pieces a fixed spread of types, so the golden counts below
+     * see every kind of interaction: super effective extra moves, pieces destroying
+     * each other and captures bouncing off
+     */
+    fn with_types(mut chess_state: ChessState) -> ChessState {
+        let types = [
+            PokemonType::Fire,
+            PokemonType::Water,
+            PokemonType::Grass,
+            PokemonType::Ghost,
+            PokemonType::Normal,
+            PokemonType::Electric,
+            PokemonType::Ground,
+            PokemonType::Steel,
+            PokemonType::Fighting,
+        ];
+        for row in 0..BOARD_SIZE {
+            for col in 0..BOARD_SIZE {
+                let piece = &mut chess_state.chessboard.board[row][col];
+                if piece.piece_type != ChessPieceType::Empty {
+                    piece.pokemon_type = types[(row * BOARD_SIZE + col) % types.len()];
+                }
+            }
+        }
+        return chess_state;
+    }
+
+    fn kiwipete() -> ChessState {
+        return position(
+            [
+                "r...k..r", "p.ppqpb.", "bn..pnp.", "...PN...", ".p..P...", "..N..Q.p", "PPPBBPPP",
+                "R...K..R",
+            ],
+            Player::White,
+        );
+    }
+
+    fn rook_endgame() -> ChessState {
+        let mut chess_state = position(
+            [
+                "........", "..p.....", "...p....", "KP.....r", ".R...p.k", "........", "....P.P.",
+                "........",
+            ],
+            Player::White,
+        );
+        chess_state.chessboard.history.has_white_king_moved = true;
+        chess_state.chessboard.history.has_black_king_moved = true;
+        return chess_state;
+    }
+
+    fn promotions() -> ChessState {
+        let mut chess_state = position(
+            [
+                "r...k..r", "Pppp.ppp", ".b...nbN", "nP......", "BBP.P...", "q....N..", "Pp.P..PP",
+                "R..Q.RK.",
+            ],
+            Player::White,
+        );
+        chess_state.chessboard.history.has_white_king_moved = true;
+        return chess_state;
+    }
+
+    fn perft_counts(chess_state: &ChessState, max_depth: usize) -> Vec<u64> {
+        return (1..=max_depth)
+            .map(|depth| perft(chess_state, depth))
+            .collect();
+    }
+
+    // known counts from regular chess, every piece is Normal type so nothing else applies
+
+    #[test]
+    fn test_perft_start_position() {
+        let chess_state =
+            ChessState::new_with_board(ChessBoard::new_normal_type_only(), Settings::default());
+        assert_eq!(perft_counts(&chess_state, 3), vec![20, 400, 8902]);
+    }
+
+    #[test]
+    fn test_perft_kiwipete() {
+        // castling both ways for both sides, pins and en passant chances
+        assert_eq!(perft_counts(&kiwipete(), 2), vec![48, 2039]);
+    }
+
+    #[test]
+    fn test_perft_rook_endgame() {
+        // discovered checks and en passant along the king's rank
+        assert_eq!(perft_counts(&rook_endgame(), 3), vec![14, 191, 2812]);
+    }
+
+    #[test]
+    fn test_perft_promotions() {
+        assert_eq!(perft_counts(&promotions(), 3), vec![6, 264, 9467]);
+    }
+
+    // golden counts for typed boards, taken from this move generator once the counts
+    // above matched. A change to them is a change to the type rules
+
+    #[test]
+    fn test_perft_typed_kiwipete() {
+        assert_eq!(perft_counts(&with_types(kiwipete()), 2), vec![48, 1928]);
+    }
+
+    #[test]
+    fn test_perft_typed_rook_endgame() {
+        assert_eq!(
+            perft_counts(&with_types(rook_endgame()), 3),
+            vec![14, 190, 2824]
+        );
+    }
+
+    #[test]
+    fn test_perft_typed_promotions() {
+        assert_eq!(
+            perft_counts(&with_types(promotions()), 3),
+            vec![5, 177, 6373]
+        );
+    }
+
+    #[test]
+    #[ignore = "slow without optimizations, run with cargo test --release -- --ignored"]
+    fn test_perft_typed_start_position() {
+        // the first capture is on the third ply, so types only change the count after it
+        let chess_state = with_types(ChessState::new_with_board(
+            ChessBoard::new_normal_type_only(),
+            Settings::default(),
+        ));
+        assert_eq!(perft_counts(&chess_state, 4), vec![20, 400, 8902, 197097]);
+    }
+}
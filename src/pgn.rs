@@ -0,0 +1,672 @@
+// PGN-style export and import of a game, so finished games can be shared and archived.
+//
+// The moves are in SAN, each capture is followed by a comment with how the types interacted,
+// {SuperEffective} or {NotVeryEffective, CriticalHit} for example, along with {Missed}
+// and {EnPassant}. The types every piece started with go into the WhiteTypes and BlackTypes tags,
+// the rules the game was played with go into tags of their own, and the seed the rolls
+// came from once the game is over.
+// An import replays every move, so a move list that doesn't fit the rules is turned down.
+
+use crate::chess_state_history::ChessStateHistory;
+use crate::chess_structs::{
+    ChessPieceType, ChessState, InfoMessage, InteractionType, Move, Player, PokemonType, Winner,
+    BOARD_SIZE,
+};
+use crate::fen::{parse_square, square_name, without_types, START_POSITION};
+use crate::game::Game;
+use crate::settings::{Settings, TimeControl, TypeAssignment};
+
+// PGN lines are kept under 80 characters
+const MAX_LINE_LENGTH: usize = 79;
+
+// tag names and values in the order they came in
+type Tags = Vec<(String, String)>;
+
+impl Game {
+    pub fn to_pgn(&self) -> String {
+        let history = &self.chess_state_history;
+        if history.state_history.is_empty() {
+            return String::new();
+        }
+        // only the game as it stands, states that were undone aren't part of it,
+        // and a drafted game starts once the last type is picked
+        let states = &history.state_history[..=history.current_state_index];
+        let drafted = states
+            .iter()
+            .position(|state| !state.is_drafting())
+            .unwrap_or(0);
+        let states = &states[drafted..];
+        let start = &states[0];
+        let end = states.last().unwrap();
+        let result = result(end.winner);
+
+        let mut tags = vec![("Event", self.name.clone()), ("Result", result.to_string())];
+        let plain_fen = without_types(&start.to_fen());
+        if plain_fen != START_POSITION {
+            tags.push(("SetUp", "1".to_string()));
+            tags.push(("FEN", plain_fen));
+        }
+        tags.push(("WhiteTypes", starting_types(start, Player::White)));
+        tags.push(("BlackTypes", starting_types(start, Player::Black)));
+        let settings = self.settings();
+        tags.push(("CriticalHits", settings.critical_hits.to_string()));
+        tags.push(("Misses", settings.misses.to_string()));
+        // critical hits and misses replay the same from the same seed, it's left out
+        // until the game is over so nobody can work out the rolls to come
+        if end.winner != Winner::NoneYet {
+            tags.push(("RngSeed", start.rng_seed.to_string()));
+        }
+        tags.push((
+            "TypeAssignment",
+            type_assignment_tag(settings.type_assignment),
+        ));
+        if let Some(time_control) = settings.time_control {
+            // base+increment in seconds, the way PGN writes it
+            tags.push((
+                "TimeControl",
+                format!(
+                    "{}+{}",
+                    seconds(time_control.base_ms),
+                    seconds(time_control.increment_ms)
+                ),
+            ));
+            if let Some(delay_ms) = time_control.delay_ms {
+                tags.push(("TimeDelay", seconds(delay_ms)));
+            }
+        }
+        if let Some(reason) = end.info_message.filter(|_| end.winner != Winner::NoneYet) {
+            tags.push(("Termination", format!("{:?}", reason)));
+        }
+
+        let mut pgn = String::new();
+        for (name, value) in tags {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value.replace('"', "'")));
+        }
+        pgn.push('\n');
+        let mut tokens = move_tokens(states);
+        tokens.push(result.to_string());
+        pgn.push_str(&wrap(tokens));
+        pgn.push('\n');
+        return pgn;
+    }
+}
+
+impl Game {
+    /**
+     * Rebuilds a game from PGN written by to_pgn. Every move is played through
+     * move_piece and select_pawn_promotion_piece from the starting types, and has to
+     * play out the way its comment says. A result the moves don't reach has to come
+     * from a resignation, an agreed draw or running out of time
+     */
+    pub fn from_pgn(name: String, pgn: &str, local_play: bool) -> Result<Game, String> {
+        let (tags, movetext) = parse_tags(pgn)?;
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(tag, _)| tag == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let mut settings = Settings::new(
+            local_play,
+            tag("CriticalHits") == Some("true"),
+            tag("Misses") == Some("true"),
+        );
+        if let Some(rng_seed) = tag("RngSeed") {
+            settings.seed = Some(
+                rng_seed
+                    .parse()
+                    .map_err(|_| format!("{} isn't a valid seed", rng_seed))?,
+            );
+        }
+        if let Some(type_assignment) = tag("TypeAssignment") {
+            settings.type_assignment = parse_type_assignment(type_assignment)?;
+        }
+        if let Some(time_control) = tag("TimeControl") {
+            settings.time_control = Some(parse_time_control(time_control, tag("TimeDelay"))?);
+        }
+
+        let mut start = ChessState::from_fen(tag("FEN").unwrap_or(START_POSITION), settings)?;
+        for (tag_name, player) in [("WhiteTypes", Player::White), ("BlackTypes", Player::Black)] {
+            if let Some(types) = tag(tag_name) {
+                apply_starting_types(&mut start, player, types)?;
+            }
+        }
+        // reading the typed position back sets up everything that depends on the types
+        let start = ChessState::from_fen(&start.to_fen(), settings)?;
+        let mut game = Game::new(name, ChessStateHistory::new_with_initial_state(start));
+
+        let tokens = movetext_tokens(movetext)?;
+        let mut index = 0;
+        while index < tokens.len() {
+            let token = &tokens[index];
+            index += 1;
+            if is_move_number(token) || is_result(token) {
+                continue;
+            }
+            if token.starts_with('{') {
+                return Err(format!("The comment {} doesn't follow a move", token));
+            }
+            let chess_state = game.get_current_state().unwrap();
+            let (m, promotion) = Move::from_san(token, &chess_state)?;
+            if !game.move_piece(m.from_row, m.from_col, m.to_row, m.to_col) {
+                return Err(format!("{} can't be played", token));
+            }
+            let played = game.get_current_state().unwrap();
+            let played_move = played.chessboard.history.last_move.unwrap_or(m);
+            if played.require_piece_selection {
+                let piece = promotion.ok_or(format!("{} doesn't say what to promote to", token))?;
+                game.select_pawn_promotion_piece(piece)
+                    .map_err(|error| format!("{}: {}", token, error))?;
+            } else if promotion.is_some() {
+                return Err(format!("{} doesn't promote", token));
+            }
+
+            let comment = tokens.get(index).filter(|next| next.starts_with('{'));
+            if comment.is_some() {
+                index += 1;
+            }
+            let expected = annotation(&played_move).map(|annotation| format!("{{{}}}", annotation));
+            if comment != expected.as_ref() {
+                return Err(format!(
+                    "{} played out as {}, not {}",
+                    token,
+                    expected.unwrap_or("{}".to_string()),
+                    comment.map(|comment| comment.as_str()).unwrap_or("{}")
+                ));
+            }
+        }
+
+        finish_game(&mut game, tag("Result").unwrap_or("*"), tag("Termination"))?;
+        return Ok(game);
+    }
+}
+
+// the result tag has to match where the moves left the game, or how it ended otherwise
+fn finish_game(game: &mut Game, result_tag: &str, termination: Option<&str>) -> Result<(), String> {
+    let chess_state = game.get_current_state().unwrap();
+    if result(chess_state.winner) == result_tag {
+        return Ok(());
+    }
+    let winner = match result_tag {
+        "1-0" => Winner::White,
+        "0-1" => Winner::Black,
+        "1/2-1/2" => Winner::Tie,
+        _ => Winner::NoneYet,
+    };
+    if chess_state.winner != Winner::NoneYet || winner == Winner::NoneYet {
+        return Err(format!("The moves don't end in {}", result_tag));
+    }
+    let loser = match winner {
+        Winner::White => Some(Player::Black),
+        Winner::Black => Some(Player::White),
+        _ => None,
+    };
+    return match (termination, loser) {
+        (Some("Resigned"), Some(loser)) => game.resign(loser),
+        (Some("DrawAgreed"), None) => {
+            game.offer_draw(chess_state.player)?;
+            game.accept_draw(chess_state.player.other_player())
+        }
+        (Some("OutOfTime"), Some(_)) => {
+            let mut chess_state = chess_state;
+            chess_state.winner = winner;
+            chess_state.info_message = Some(InfoMessage::OutOfTime);
+            game.chess_state_history.add_state(chess_state);
+            Ok(())
+        }
+        _ => Err(format!("The moves don't end in {}", result_tag)),
+    };
+}
+
+// [Name "Value"] lines up to the first blank line, and the movetext after them
+fn parse_tags(pgn: &str) -> Result<(Tags, &str), String> {
+    let mut tags = Vec::new();
+    let mut rest = pgn.trim_start();
+    while rest.starts_with('[') {
+        let (line, remaining) = rest.split_once('\n').unwrap_or((rest, ""));
+        let (name, value) = line
+            .trim()
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+            .and_then(|line| line.split_once(' '))
+            .ok_or(format!("{} isn't a valid tag", line))?;
+        let value = value.trim().trim_matches('"');
+        tags.push((name.to_string(), value.to_string()));
+        rest = remaining.trim_start();
+    }
+    return Ok((tags, rest));
+}
+
+// the movetext split on whitespace, except a {comment} stays one token
+fn movetext_tokens(movetext: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = movetext.trim();
+    while !rest.is_empty() {
+        let end = if rest.starts_with('{') {
+            rest.find('}')
+                .ok_or("A comment is missing its }".to_string())?
+                + 1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || c == '{')
+                .unwrap_or(rest.len())
+        };
+        tokens.push(rest[..end].to_string());
+        rest = rest[end..].trim_start();
+    }
+    return Ok(tokens);
+}
+
+fn is_move_number(token: &str) -> bool {
+    return token.ends_with('.') && token.trim_end_matches('.').parse::<u32>().is_ok();
+}
+
+fn is_result(token: &str) -> bool {
+    return matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*");
+}
+
+// the other side of starting_types, Ra1 Fire, Nb1 Water, ...
+fn apply_starting_types(start: &mut ChessState, player: Player, types: &str) -> Result<(), String> {
+    for entry in types
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let invalid = || format!("{} isn't a valid starting type", entry);
+        let (piece, pokemon_type) = entry.split_once(' ').ok_or_else(invalid)?;
+        let letter = piece.chars().next().ok_or_else(invalid)?;
+        let (row, col) = parse_square(&piece[letter.len_utf8()..]).ok_or_else(invalid)?;
+        let pokemon_type = PokemonType::from_name(pokemon_type).ok_or_else(invalid)?;
+        let board_piece = &mut start.chessboard.board[row][col];
+        if board_piece.piece_type == ChessPieceType::Empty
+            || board_piece.piece_type.get_piece_player() != player
+            || board_piece.piece_type.fen_char().to_ascii_uppercase() != letter
+        {
+            return Err(format!("{} doesn't match the starting position", entry));
+        }
+        board_piece.pokemon_type = pokemon_type;
+    }
+    return Ok(());
+}
+
+// the name /start takes, AllOneType with the type after it
+fn type_assignment_tag(type_assignment: TypeAssignment) -> String {
+    return match type_assignment {
+        TypeAssignment::AllOneType(pokemon_type) => format!("AllOneType {}", pokemon_type),
+        type_assignment => format!("{:?}", type_assignment),
+    };
+}
+
+fn parse_type_assignment(tag: &str) -> Result<TypeAssignment, String> {
+    return match tag.split_once(' ') {
+        Some((name, pokemon_type)) => {
+            let pokemon_type = PokemonType::from_name(pokemon_type)
+                .ok_or(format!("{} isn't a type", pokemon_type))?;
+            TypeAssignment::from_name(name, Some(pokemon_type))
+        }
+        None => TypeAssignment::from_name(tag, None),
+    };
+}
+
+// 300, or 2.5 for a time that isn't whole seconds
+fn seconds(ms: u64) -> String {
+    let (whole, fraction_ms) = (ms / 1000, ms % 1000);
+    if fraction_ms == 0 {
+        return whole.to_string();
+    }
+    return format!("{}.{:03}", whole, fraction_ms);
+}
+
+fn parse_seconds(seconds: &str) -> Option<u64> {
+    let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || !digits(fraction) || fraction.len() > 3 {
+        return None;
+    }
+    let fraction_ms: u64 = format!("{:0<3}", fraction).parse().ok()?;
+    return whole
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(1000)?
+        .checked_add(fraction_ms);
+}
+
+fn parse_time_control(time_control: &str, delay: Option<&str>) -> Result<TimeControl, String> {
+    let invalid = || format!("{} isn't a valid time control", time_control);
+    let (base, increment) = time_control.split_once('+').unwrap_or((time_control, "0"));
+    let base_ms = parse_seconds(base).ok_or_else(invalid)?;
+    let increment_ms = parse_seconds(increment).ok_or_else(invalid)?;
+    let delay_ms = match delay {
+        Some(delay) => Some(parse_seconds(delay).ok_or(format!("{} isn't a valid delay", delay))?),
+        None => None,
+    };
+    return Ok(TimeControl::new(base_ms, increment_ms, delay_ms));
+}
+
+pub fn result(winner: Winner) -> &'static str {
+    return match winner {
+        Winner::White => "1-0",
+        Winner::Black => "0-1",
+        Winner::Tie => "1/2-1/2",
+        Winner::NoneYet => "*",
+    };
+}
+
+// Ra1 Fire, Nb1 Water, ... for every piece the player starts with
+fn starting_types(start: &ChessState, player: Player) -> String {
+    let mut types = Vec::new();
+    for row in 0..BOARD_SIZE {
+        for col in 0..BOARD_SIZE {
+            let piece = start.chessboard.get_piece(row, col);
+            if piece.piece_type == ChessPieceType::Empty
+                || piece.piece_type.get_piece_player() != player
+            {
+                continue;
+            }
+            types.push(format!(
+                "{}{} {}",
+                piece.piece_type.fen_char().to_ascii_uppercase(),
+                square_name(row, col),
+                piece.pokemon_type
+            ));
+        }
+    }
+    return types.join(", ");
+}
+
+/**
+ * Walks the history a move at a time. A state only holds a move if the turn count went up,
+ * resigning or offering a draw saves a state without one, and a promotion is only known
+ * once the state after the move that reached the last rank
+ */
+fn move_tokens(states: &[ChessState]) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut move_number = states[0].turn_count / 2 + 1;
+    // black's moves are numbered n... unless they come straight after white's
+    let mut number_black_move = true;
+    let mut index = 0;
+    while index + 1 < states.len() {
+        let before = &states[index];
+        let mut after = &states[index + 1];
+        index += 1;
+        if after.turn_count == before.turn_count {
+            continue;
+        }
+        let m = match after.chessboard.history.last_move {
+            Some(m) => m,
+            None => continue,
+        };
+        if after.require_piece_selection
+            && index + 1 < states.len()
+            && states[index + 1].turn_count == after.turn_count
+        {
+            after = &states[index + 1];
+            index += 1;
+        }
+
+        let mover = before.player;
+        if mover == Player::White {
+            tokens.push(format!("{}.", move_number));
+        } else if number_black_move {
+            tokens.push(format!("{}...", move_number));
+        }
+
+        let mut san = m.to_san(before);
+        let promoted_to = after.chessboard.get_piece(m.to_row, m.to_col).piece_type;
+        // a pawn destroyed on the way in never gets promoted
+        if m.is_promotion()
+            && !m.missed
+            && promoted_to != ChessPieceType::Empty
+            && promoted_to != m.piece_type
+        {
+            san.push('=');
+            san.push(promoted_to.fen_char().to_ascii_uppercase());
+        }
+        if after.winner == Winner::from_player(mover) {
+            san.push('#');
+        } else if after.chessboard.is_king_in_check(mover.other_player()) {
+            san.push('+');
+        }
+        tokens.push(san);
+
+        let comment = annotation(&m);
+        let has_comment = comment.is_some();
+        if let Some(comment) = comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+
+        if mover == Player::Black {
+            move_number += 1;
+        }
+        number_black_move = mover == Player::Black || has_comment;
+    }
+    return tokens;
+}
+
+// how the move played out, for the comment after it
+fn annotation(m: &Move) -> Option<String> {
+    let mut annotations = Vec::new();
+    if m.capture.is_some() {
+        let interaction = m.type_interaction.unwrap_or(InteractionType::Normal);
+        annotations.push(format!("{:?}", interaction));
+    }
+    if m.critical_hit {
+        annotations.push("CriticalHit".to_string());
+    }
+    if m.missed {
+        annotations.push("Missed".to_string());
+    }
+    if m.is_en_passant() {
+        annotations.push("EnPassant".to_string());
+    }
+    if annotations.is_empty() {
+        return None;
+    }
+    return Some(annotations.join(", "));
+}
+
+fn wrap(tokens: Vec<String>) -> String {
+    let mut lines = vec![String::new()];
+    for token in tokens {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+            lines.push(token);
+            continue;
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    return lines.join("\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_state_history::ChessStateHistory;
+    use crate::settings::Settings;
+
+    fn game_from(fen: &str) -> Game {
+        let chess_state = ChessState::from_fen(fen, Settings::default()).unwrap();
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        return Game::new("test".to_string(), chess_state_history);
+    }
+
+    #[test]
+    fn test_fools_mate() {
+        let mut game = game_from(START_POSITION);
+        game.move_piece(1, 5, 2, 5);
+        game.move_piece(6, 4, 4, 4);
+        game.move_piece(1, 6, 3, 6);
+        game.move_piece(7, 3, 3, 7);
+        let pgn = game.to_pgn();
+        assert!(pgn.starts_with("[Event \"test\"]\n[Result \"0-1\"]\n"));
+        assert!(pgn.contains("[WhiteTypes \"Ra1 Normal, Nb1 Normal, "));
+        assert!(!pgn.contains("[FEN"));
+        assert!(pgn.ends_with("\n1. f3 e5 2. g4 Qh4# 0-1\n"));
+    }
+
+    #[test]
+    fn test_annotations_and_promotion() {
+        let mut game = game_from("4k3/1P6/8/3p{Water}4/4P{Fire}3/8/8/4K3 w - - 0 1");
+        // fire into water destroys both pawns
+        game.move_piece(3, 4, 4, 3);
+        game.move_piece(7, 4, 7, 3);
+        game.move_piece(6, 1, 7, 1);
+        game.select_pawn_promotion_piece("Queen".to_string())
+            .unwrap();
+        let pgn = game.to_pgn();
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/1P6/8/3p4/4P3/8/8/4K3 w - - 0 1\"]"));
+        assert!(pgn.contains("[BlackTypes \"Pd5 Water, Ke8 Normal\"]"));
+        assert!(pgn.contains("1. exd5 {NotVeryEffective} 1... Kd8 2. b8=Q+ *"));
+    }
+
+    #[test]
+    fn test_undone_moves_are_left_out() {
+        let mut game = game_from(START_POSITION);
+        game.move_piece(1, 4, 3, 4);
+        game.move_piece(6, 4, 4, 4);
+        game.get_previous_state();
+        assert!(game.to_pgn().ends_with("\n1. e4 *\n"));
+    }
+
+    fn round_trip(game: &Game) -> Game {
+        let imported = Game::from_pgn("imported".to_string(), &game.to_pgn(), true).unwrap();
+        let expected = game.get_current_state().unwrap();
+        let chess_state = imported.get_current_state().unwrap();
+        assert_eq!(chess_state.to_fen(), expected.to_fen());
+        assert_eq!(chess_state.winner, expected.winner);
+        assert_eq!(imported.to_pgn().replace("imported", "test"), game.to_pgn());
+        assert_eq!(
+            imported.settings().time_control,
+            game.settings().time_control
+        );
+        assert_eq!(
+            imported.settings().type_assignment,
+            game.settings().type_assignment
+        );
+        return imported;
+    }
+
+    #[test]
+    fn test_import_round_trip() {
+        let mut game = game_from(START_POSITION);
+        game.move_piece(1, 5, 2, 5);
+        game.move_piece(6, 4, 4, 4);
+        game.move_piece(1, 6, 3, 6);
+        game.move_piece(7, 3, 3, 7);
+        let imported = round_trip(&game);
+        assert_eq!(imported.chess_state_history.state_history.len(), 5);
+
+        let mut game = game_from("4k3/1P6/8/3p{Water}4/4P{Fire}3/8/8/4K3 w - - 0 1");
+        game.move_piece(3, 4, 4, 3);
+        game.move_piece(7, 4, 7, 3);
+        game.move_piece(6, 1, 7, 1);
+        game.select_pawn_promotion_piece("Knight".to_string())
+            .unwrap();
+        round_trip(&game);
+    }
+
+    #[test]
+    fn test_import_with_rolls_and_resignation() {
+        let settings = Settings::new(true, true, true);
+        let chess_state = ChessState::new_with_settings(settings);
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        let mut game = Game::new("test".to_string(), chess_state_history);
+        // every capture rolls for a critical hit and a miss, the seed replays them
+        for _ in 0..40 {
+            let chess_state = game.get_current_state().unwrap();
+            let moves = chess_state.all_legal_moves();
+            if moves.is_empty() {
+                break;
+            }
+            let m = moves
+                .iter()
+                .find(|m| m.capture.is_some())
+                .unwrap_or(&moves[0]);
+            game.move_piece(m.from_row, m.from_col, m.to_row, m.to_col);
+            if game.get_current_state().unwrap().require_piece_selection {
+                game.select_pawn_promotion_piece("Queen".to_string())
+                    .unwrap();
+            }
+        }
+        if game.get_current_state().unwrap().winner == Winner::NoneYet {
+            game.resign(Player::White).unwrap();
+        }
+        round_trip(&game);
+    }
+
+    #[test]
+    fn test_import_keeps_the_rules() {
+        let settings = Settings {
+            time_control: Some(TimeControl::new(300_000, 5000, Some(2500))),
+            seed: Some(7),
+            type_assignment: TypeAssignment::AllOneType(PokemonType::Fire),
+            ..Settings::new(true, true, false)
+        };
+        let chess_state = ChessState::new_with_settings(settings);
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        let mut game = Game::new("test".to_string(), chess_state_history);
+        game.move_piece(1, 4, 3, 4);
+        let pgn = game.to_pgn();
+        assert!(pgn.contains("[TypeAssignment \"AllOneType Fire\"]"));
+        assert!(pgn.contains("[TimeControl \"300+5\"]\n[TimeDelay \"2.500\"]"));
+        // the rolls to come can't be worked out from a game still being played
+        assert!(!pgn.contains("RngSeed"));
+
+        game.resign(Player::Black).unwrap();
+        assert!(game.to_pgn().contains("[RngSeed \"7\"]"));
+        let imported = round_trip(&game);
+        assert_eq!(imported.settings().seed, Some(7));
+        assert!(imported.settings().critical_hits && !imported.settings().misses);
+        assert!(imported.get_current_state().unwrap().clock.is_some());
+
+        // PGN from before the rules had tags plays with the defaults
+        let pgn = "[Result \"*\"]\n\n1. e4 *\n";
+        let imported = Game::from_pgn("test".to_string(), pgn, true).unwrap();
+        assert_eq!(imported.settings().time_control, None);
+        assert_eq!(imported.settings().type_assignment, TypeAssignment::Random);
+    }
+
+    #[test]
+    fn test_bad_rule_tags_are_turned_down() {
+        for tags in [
+            "[TimeControl \"five minutes\"]",
+            "[TimeControl \"300+5\"]\n[TimeDelay \"2.5555\"]",
+            "[TypeAssignment \"Shuffled\"]",
+            "[TypeAssignment \"AllOneType Plasma\"]",
+        ] {
+            let pgn = format!("{}\n\n1. e4 *\n", tags);
+            assert!(Game::from_pgn("test".to_string(), &pgn, true).is_err());
+        }
+        assert_eq!(parse_seconds("2.5"), Some(2500));
+        assert_eq!(parse_seconds("+2"), None);
+        assert_eq!(parse_seconds(&u64::MAX.to_string()), None);
+    }
+
+    #[test]
+    fn test_import_turns_down_bad_moves() {
+        let pgn = "[Result \"*\"]\n\n1. e4 e5 2. Ke3 *\n";
+        let error = Game::from_pgn("test".to_string(), pgn, true).unwrap_err();
+        assert!(error.contains("Ke3"));
+
+        // fire into water destroys both pawns, it can't be a plain capture
+        let mut game = game_from("r3k3/8/8/3p{Water}4/4P{Fire}3/8/8/R3K3 w - - 0 1");
+        game.move_piece(3, 4, 4, 3);
+        let pgn = game.to_pgn().replace("{NotVeryEffective}", "{Normal}");
+        let error = Game::from_pgn("test".to_string(), &pgn, true).unwrap_err();
+        assert!(error.contains("exd5"));
+
+        let pgn = "[WhiteTypes \"Pe2 Plasma\"]\n\n1. e4 *\n";
+        let error = Game::from_pgn("test".to_string(), pgn, true).unwrap_err();
+        assert!(error.contains("Pe2 Plasma"));
+        let pgn = "[WhiteTypes \"Pz9 Fire\"]\n\n1. e4 *\n";
+        let error = Game::from_pgn("test".to_string(), pgn, true).unwrap_err();
+        assert!(error.contains("Pz9 Fire"));
+        let pgn = "[FEN \"4k3/1P6/8/8/8/8/8/4K3 w - - 0 1\"]\n\n1. b8 *\n";
+        let error = Game::from_pgn("test".to_string(), pgn, true).unwrap_err();
+        assert!(error.contains("doesn't say what to promote to"));
+
+        let pgn = game.to_pgn().replace('*', "1-0");
+        assert!(Game::from_pgn("test".to_string(), &pgn, true).is_err());
+    }
+}
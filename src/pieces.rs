@@ -79,6 +79,43 @@ impl ChessPieceType {
             _ => false,
         }
     }
+    // Upper case for white, lower case for black, like FEN
+    pub fn fen_char(&self) -> char {
+        match self {
+            ChessPieceType::Empty => '.',
+            ChessPieceType::WhitePawn => 'P',
+            ChessPieceType::WhiteKnight => 'N',
+            ChessPieceType::WhiteBishop => 'B',
+            ChessPieceType::WhiteRook => 'R',
+            ChessPieceType::WhiteQueen => 'Q',
+            ChessPieceType::WhiteKing => 'K',
+            ChessPieceType::BlackPawn => 'p',
+            ChessPieceType::BlackKnight => 'n',
+            ChessPieceType::BlackBishop => 'b',
+            ChessPieceType::BlackRook => 'r',
+            ChessPieceType::BlackQueen => 'q',
+            ChessPieceType::BlackKing => 'k',
+        }
+    }
+    // the inverse of fen_char, None for anything that isn't a piece letter
+    pub fn from_fen_char(letter: char) -> Option<ChessPieceType> {
+        let piece_type = match letter {
+            'P' => ChessPieceType::WhitePawn,
+            'N' => ChessPieceType::WhiteKnight,
+            'B' => ChessPieceType::WhiteBishop,
+            'R' => ChessPieceType::WhiteRook,
+            'Q' => ChessPieceType::WhiteQueen,
+            'K' => ChessPieceType::WhiteKing,
+            'p' => ChessPieceType::BlackPawn,
+            'n' => ChessPieceType::BlackKnight,
+            'b' => ChessPieceType::BlackBishop,
+            'r' => ChessPieceType::BlackRook,
+            'q' => ChessPieceType::BlackQueen,
+            'k' => ChessPieceType::BlackKing,
+            _ => return None,
+        };
+        return Some(piece_type);
+    }
     pub fn is_king(&self) -> bool {
         return *self == ChessPieceType::WhiteKing || *self == ChessPieceType::BlackKing;
     }
@@ -122,6 +159,8 @@ impl ChessPieceType {
                 type_interaction: None,
                 capture: None,
                 castle: None,
+                critical_hit: false,
+                missed: false,
             });
         }
 
@@ -146,6 +185,8 @@ impl ChessPieceType {
                         piece,
                     }),
                     castle: None,
+                    critical_hit: false,
+                    missed: false,
                 });
             }
         }
@@ -166,6 +207,8 @@ impl ChessPieceType {
                         piece,
                     }),
                     castle: None,
+                    critical_hit: false,
+                    missed: false,
                 });
             }
         }
@@ -196,6 +239,8 @@ impl ChessPieceType {
                 type_interaction: None,
                 capture: None,
                 castle: None,
+                critical_hit: false,
+                missed: false,
             });
         }
 
@@ -233,6 +278,8 @@ impl ChessPieceType {
                         piece: board.get_piece(row, en_passant_col), // Captured pawn
                     }),
                     castle: None,
+                    critical_hit: false,
+                    missed: false,
                 });
             }
         }
@@ -281,6 +328,8 @@ impl ChessPieceType {
                             })
                         },
                         castle: None,
+                        critical_hit: false,
+                        missed: false,
                     });
                 }
             }
@@ -323,6 +372,8 @@ impl ChessPieceType {
                             type_interaction: None,
                             capture: None,
                             castle: None,
+                            critical_hit: false,
+                            missed: false,
                         });
                     }
                     // Square occupied by another piece
@@ -342,6 +393,8 @@ impl ChessPieceType {
                                     piece: board.get_piece(new_row as usize, new_row as usize),
                                 }),
                                 castle: None,
+                                critical_hit: false,
+                                missed: false,
                             })
                         }
                         // Stop either way, can't jump over pieces
@@ -388,6 +441,8 @@ impl ChessPieceType {
                             type_interaction: None,
                             capture: None,
                             castle: None,
+                            critical_hit: false,
+                            missed: false,
                         });
                     }
                     // Square occupied by another piece
@@ -407,6 +462,8 @@ impl ChessPieceType {
                                     piece: target_square.clone(),
                                 }),
                                 castle: None,
+                                critical_hit: false,
+                                missed: false,
                             });
                         }
                         break; // Bishop's path is blocked
@@ -468,6 +525,8 @@ impl ChessPieceType {
                             })
                         },
                         castle: None,
+                        critical_hit: false,
+                        missed: false,
                     });
                 }
             }
@@ -490,6 +549,8 @@ impl ChessPieceType {
                     rook_to_row: row,
                     rook_to_col: 5,
                 }),
+                critical_hit: false,
+                missed: false,
             });
         }
         if !only_capture_moves && board.can_castle_queenside(row, self.get_piece_player()) {
@@ -507,6 +568,8 @@ impl ChessPieceType {
                     rook_to_row: row,
                     rook_to_col: 3,
                 }),
+                critical_hit: false,
+                missed: false,
             });
         }
         moves
@@ -1,9 +1,32 @@
 use crate::chess_structs::{InteractionType, PokemonType};
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::Rng;
 use std::fmt;
 
 impl PokemonType {
+    // every type a piece can have, NoType is only for empty squares
+    // duplication sucks but there's no way to count enum variants without a macro
+    pub const ALL: [PokemonType; 18] = [
+        PokemonType::Normal,
+        PokemonType::Fire,
+        PokemonType::Water,
+        PokemonType::Electric,
+        PokemonType::Grass,
+        PokemonType::Ice,
+        PokemonType::Fighting,
+        PokemonType::Poison,
+        PokemonType::Ground,
+        PokemonType::Flying,
+        PokemonType::Psychic,
+        PokemonType::Bug,
+        PokemonType::Rock,
+        PokemonType::Ghost,
+        PokemonType::Dragon,
+        PokemonType::Dark,
+        PokemonType::Steel,
+        PokemonType::Fairy,
+    ];
+
     pub fn get_type_chart() -> [[InteractionType; 19]; 19] {
         let type_chart: [[InteractionType; 19]; 19] = [
             // Normal
@@ -434,31 +457,35 @@ impl PokemonType {
         return Self::get_type_chart()[type1 as usize][type2 as usize];
     }
 
-    pub fn random() -> Self {
-        // duplication sucks but there's no way to count enum variants without a macro
-        let variants = [
-            PokemonType::Normal,
-            PokemonType::Fire,
-            PokemonType::Water,
-            PokemonType::Electric,
-            PokemonType::Grass,
-            PokemonType::Ice,
-            PokemonType::Fighting,
-            PokemonType::Poison,
-            PokemonType::Ground,
-            PokemonType::Flying,
-            PokemonType::Psychic,
-            PokemonType::Bug,
-            PokemonType::Rock,
-            PokemonType::Ghost,
-            PokemonType::Dragon,
-            PokemonType::Dark,
-            PokemonType::Steel,
-            PokemonType::Fairy,
-        ];
+    // the inverse of Display, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        let pokemon_type = match name.to_lowercase().as_str() {
+            "normal" => PokemonType::Normal,
+            "fire" => PokemonType::Fire,
+            "water" => PokemonType::Water,
+            "electric" => PokemonType::Electric,
+            "grass" => PokemonType::Grass,
+            "ice" => PokemonType::Ice,
+            "fighting" => PokemonType::Fighting,
+            "poison" => PokemonType::Poison,
+            "ground" => PokemonType::Ground,
+            "flying" => PokemonType::Flying,
+            "psychic" => PokemonType::Psychic,
+            "bug" => PokemonType::Bug,
+            "rock" => PokemonType::Rock,
+            "ghost" => PokemonType::Ghost,
+            "dragon" => PokemonType::Dragon,
+            "dark" => PokemonType::Dark,
+            "steel" => PokemonType::Steel,
+            "fairy" => PokemonType::Fairy,
+            "notype" => PokemonType::NoType,
+            _ => return None,
+        };
+        return Some(pokemon_type);
+    }
 
-        let mut rng = thread_rng();
-        return *variants.choose(&mut rng).expect("Variant array is empty!");
+    pub fn random(rng: &mut impl Rng) -> Self {
+        return *PokemonType::ALL.choose(rng).expect("Variant array is empty!");
     }
 }
 
@@ -0,0 +1,406 @@
+// How a game is written to the game store.
+//
+// A save keeps the first state of the history and the steps played from it: moves with
+// the critical hits and misses they were played with, promotion picks, draft picks,
+// resignations, draw offers and running out of time. The states are rebuilt by replaying
+// the steps when the game is loaded. A change none of those steps make is kept as the
+// whole state it led to. Saves in older formats are brought up to date by
+// save_migrations.rs before they're read.
+
+use crate::chess_state_history::ChessStateHistory;
+use crate::chess_structs::{
+    ChessClock, ChessState, InfoMessage, MoveRolls, Player, PokemonType, Winner, BOARD_SIZE,
+};
+use crate::fen::{parse_square, square_name};
+use crate::game::Game;
+use crate::notation::promotion_piece_name;
+use crate::save_migrations::{migrate, CURRENT_FORMAT};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize)]
+struct CompactGame {
+    // the header, see save_migrations.rs
+    format: u32,
+    name: String,
+    // at the top level like in a snapshot, the stores read it back to check saves
+    version: u64,
+    start: ChessState,
+    steps: Vec<Step>,
+    current_state_index: usize,
+}
+
+// one step leads from a state in the history to the next one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Step {
+    action: Action,
+    // the clock depends on when the step was played, it's kept as it was instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clock: Option<ChessClock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Action {
+    // played with the rolls it was played with, they aren't rolled again
+    Move {
+        from: String,
+        to: String,
+        #[serde(default, skip_serializing_if = "is_false")]
+        critical_hit: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        missed: bool,
+    },
+    Promote(String),
+    DraftType {
+        square: String,
+        pokemon_type: PokemonType,
+    },
+    Resign(Player),
+    OfferDraw(Player),
+    AcceptDraw(Player),
+    DeclineDraw(Player),
+    // the player who ran out of time
+    OutOfTime(Player),
+    // the state as it was, for a change the other steps don't make
+    Snapshot(Box<ChessState>),
+}
+
+fn is_false(value: &bool) -> bool {
+    return !*value;
+}
+
+impl Game {
+    // the game as it's written to the store, see the top of save_format.rs
+    pub fn to_save(&self) -> Result<String, String> {
+        let compact = CompactGame::from_game(self)?;
+        return serde_json::to_string(&compact).map_err(|err| err.to_string());
+    }
+
+    // reads a save in any format there has been
+    pub fn from_save(save: &str) -> Result<Game, String> {
+        let save: Value = serde_json::from_str(save).map_err(|err| err.to_string())?;
+        let compact: CompactGame =
+            serde_json::from_value(migrate(save)?).map_err(|err| err.to_string())?;
+        return compact.into_game();
+    }
+}
+
+impl CompactGame {
+    fn from_game(game: &Game) -> Result<CompactGame, String> {
+        let states = &game.chess_state_history.state_history;
+        let start = match states.first() {
+            Some(start) => start.clone(),
+            None => return Err(format!("{} has no states to save", game.name)),
+        };
+        let steps = states
+            .windows(2)
+            .map(|pair| Step::leading_to(&pair[0], &pair[1]))
+            .collect();
+        return Ok(CompactGame {
+            format: CURRENT_FORMAT,
+            name: game.name.clone(),
+            version: game.version,
+            start,
+            steps,
+            current_state_index: game.chess_state_history.current_state_index,
+        });
+    }
+
+    fn replay(&self) -> Result<Vec<ChessState>, String> {
+        let mut states = vec![self.start.clone()];
+        for (index, step) in self.steps.iter().enumerate() {
+            let mut chess_state = states.last().unwrap().clone();
+            step.apply(&mut chess_state)
+                .map_err(|err| format!("Step {} can't be replayed: {}", index + 1, err))?;
+            states.push(chess_state);
+        }
+        return Ok(states);
+    }
+
+    fn into_game(self) -> Result<Game, String> {
+        let state_history = self.replay()?;
+        if self.current_state_index >= state_history.len() {
+            return Err(format!(
+                "The current state {} is past the end of the game",
+                self.current_state_index
+            ));
+        }
+        let chess_state_history = ChessStateHistory {
+            state_history,
+            current_state_index: self.current_state_index,
+        };
+        let mut game = Game::new(self.name, chess_state_history);
+        game.version = self.version;
+        return Ok(game);
+    }
+}
+
+impl Step {
+    // the step from one state to the next, the whole state if no other step replays it exactly
+    fn leading_to(before: &ChessState, after: &ChessState) -> Step {
+        let replays = |step: &Step| {
+            let mut chess_state = before.clone();
+            return step.apply(&mut chess_state).is_ok() && chess_state == *after;
+        };
+        return match Step::between(before, after).filter(replays) {
+            Some(step) => step,
+            None => Step {
+                action: Action::Snapshot(Box::new(after.clone())),
+                clock: None,
+            },
+        };
+    }
+
+    // works out what was played from the state before and the state after
+    fn between(before: &ChessState, after: &ChessState) -> Option<Step> {
+        let action = if after.turn_count == before.turn_count + 1 {
+            let m = after.chessboard.history.last_move()?;
+            Action::Move {
+                from: square_name(m.from_row, m.from_col),
+                to: square_name(m.to_row, m.to_col),
+                critical_hit: m.critical_hit,
+                missed: m.missed,
+            }
+        } else if before.require_piece_selection && !after.require_piece_selection {
+            let m = after.chessboard.history.last_move()?;
+            let piece = after.chessboard.get_piece(m.to_row, m.to_col).piece_type;
+            Action::Promote(promotion_piece_name(piece.fen_char())?.to_string())
+        } else if before.is_drafting() {
+            let (row, col) = changed_type(before, after)?;
+            Action::DraftType {
+                square: square_name(row, col),
+                pokemon_type: after.chessboard.get_piece(row, col).pokemon_type,
+            }
+        } else if before.winner == Winner::NoneYet && after.winner != Winner::NoneYet {
+            match after.info_message? {
+                InfoMessage::Resigned => Action::Resign(loser(after.winner)?),
+                InfoMessage::OutOfTime => Action::OutOfTime(loser(after.winner)?),
+                InfoMessage::DrawAgreed => Action::AcceptDraw(before.draw_offer?.other_player()),
+                _ => return None,
+            }
+        } else {
+            match (before.draw_offer, after.draw_offer) {
+                (None, Some(player)) => Action::OfferDraw(player),
+                (Some(player), None) => Action::DeclineDraw(player.other_player()),
+                _ => return None,
+            }
+        };
+        return Some(Step {
+            action,
+            clock: after.clock,
+        });
+    }
+
+    fn apply(&self, chess_state: &mut ChessState) -> Result<(), String> {
+        match &self.action {
+            Action::Move {
+                from,
+                to,
+                critical_hit,
+                missed,
+            } => {
+                let (from_row, from_col) = square(from)?;
+                let (to_row, to_col) = square(to)?;
+                let rolls = MoveRolls {
+                    critical_hit: *critical_hit,
+                    miss: *missed,
+                };
+                if !chess_state.move_piece_with_rolls(from_row, from_col, to_row, to_col, rolls) {
+                    return Err(format!("{}{} isn't a legal move", from, to));
+                }
+            }
+            Action::Promote(piece) => chess_state.select_pawn_promotion_piece(piece.clone())?,
+            Action::DraftType {
+                square: name,
+                pokemon_type,
+            } => {
+                let (row, col) = square(name)?;
+                chess_state.draft_type(row, col, *pokemon_type)?;
+            }
+            Action::Resign(player) => chess_state.resign(*player)?,
+            Action::OfferDraw(player) => chess_state.offer_draw(*player)?,
+            Action::AcceptDraw(player) => chess_state.accept_draw(*player)?,
+            Action::DeclineDraw(player) => chess_state.decline_draw(*player)?,
+            Action::OutOfTime(player) => {
+                if chess_state.winner != Winner::NoneYet {
+                    return Err("The game is already over".to_string());
+                }
+                chess_state.winner = Winner::from_player(player.other_player());
+                chess_state.info_message = Some(InfoMessage::OutOfTime);
+                chess_state.draw_offer = None;
+            }
+            Action::Snapshot(snapshot) => {
+                // comes with its own clock
+                *chess_state = (**snapshot).clone();
+                return Ok(());
+            }
+        }
+        chess_state.clock = self.clock;
+        return Ok(());
+    }
+}
+
+fn square(name: &str) -> Result<(usize, usize), String> {
+    return parse_square(name).ok_or(format!("{} isn't a square", name));
+}
+
+fn loser(winner: Winner) -> Option<Player> {
+    return match winner {
+        Winner::White => Some(Player::Black),
+        Winner::Black => Some(Player::White),
+        _ => None,
+    };
+}
+
+// the square a draft pick gave its type to
+fn changed_type(before: &ChessState, after: &ChessState) -> Option<(usize, usize)> {
+    for row in 0..BOARD_SIZE {
+        for col in 0..BOARD_SIZE {
+            let before_type = before.chessboard.get_piece(row, col).pokemon_type;
+            if after.chessboard.get_piece(row, col).pokemon_type != before_type {
+                return Some((row, col));
+            }
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{Settings, TimeControl, TypeAssignment};
+
+    fn game_with(chess_state: ChessState) -> Game {
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        return Game::new("test".to_string(), chess_state_history);
+    }
+
+    fn steps(game: &Game) -> Vec<Step> {
+        return CompactGame::from_game(game).unwrap().steps;
+    }
+
+    fn is_snapshot(step: &Step) -> bool {
+        return matches!(step.action, Action::Snapshot(_));
+    }
+
+    // saved as steps, and loaded back state for state
+    fn check_round_trip(game: &Game) {
+        assert!(!steps(game).iter().any(is_snapshot));
+        let save = game.to_save().unwrap();
+        assert_eq!(Game::from_save(&save).unwrap(), *game);
+    }
+
+    #[test]
+    fn test_moves_promotion_draw_offers_and_undo() {
+        let chess_state =
+            ChessState::from_fen("4k3/P7/8/8/8/8/8/R3K3 w - - 0 1", Settings::default()).unwrap();
+        let mut game = game_with(chess_state);
+        game.move_piece(6, 0, 7, 0);
+        game.select_pawn_promotion_piece("Queen".to_string())
+            .unwrap();
+        game.move_piece(7, 4, 6, 4);
+        game.offer_draw(Player::White).unwrap();
+        game.decline_draw(Player::Black).unwrap();
+        game.offer_draw(Player::Black).unwrap();
+        game.accept_draw(Player::White).unwrap();
+        check_round_trip(&game);
+
+        // the undone states are kept too
+        game.get_previous_state();
+        game.get_previous_state();
+        check_round_trip(&game);
+        game.resign(Player::Black).unwrap();
+        check_round_trip(&game);
+    }
+
+    #[test]
+    fn test_rolls_are_kept() {
+        let settings = Settings::new(true, true, true);
+        let mut chess_state =
+            ChessState::from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", settings).unwrap();
+        let missing_seed = (0..)
+            .find(|seed| MoveRolls::roll(*seed, chess_state.turn_count).miss)
+            .unwrap();
+        chess_state.rng_seed = missing_seed;
+        let mut game = game_with(chess_state);
+        game.move_piece(3, 4, 4, 3);
+        assert!(
+            game.get_current_state()
+                .unwrap()
+                .chessboard
+                .history
+                .last_move()
+                .unwrap()
+                .missed
+        );
+        check_round_trip(&game);
+
+        // a save is replayed with the rolls in it, whatever the seed would roll now
+        let save = game.to_save().unwrap();
+        let mut compact: CompactGame = serde_json::from_str(&save).unwrap();
+        compact.start.rng_seed = (0..).find(|seed| !MoveRolls::roll(*seed, 0).miss).unwrap();
+        let loaded = compact.into_game().unwrap();
+        assert_eq!(
+            loaded.get_current_state().unwrap().chessboard,
+            game.get_current_state().unwrap().chessboard
+        );
+    }
+
+    #[test]
+    fn test_draft_with_clock_and_running_out_of_time() {
+        let settings = Settings {
+            type_assignment: TypeAssignment::Draft,
+            time_control: Some(TimeControl {
+                base_ms: 60_000,
+                increment_ms: 1_000,
+                delay_ms: None,
+            }),
+            ..Settings::default()
+        };
+        let mut game = game_with(ChessState::new_with_settings(settings));
+        while game.get_current_state().unwrap().is_drafting() {
+            let (row, col, pokemon_type) =
+                crate::ai::draft_pick(&game.get_current_state().unwrap()).unwrap();
+            game.draft_type(row, col, pokemon_type).unwrap();
+        }
+        game.move_piece(1, 4, 3, 4);
+        game.move_piece(6, 4, 4, 4);
+        let mut chess_state = game.get_current_state().unwrap();
+        assert!(chess_state.flag_if_out_of_time(u64::MAX));
+        game.chess_state_history.add_state(chess_state);
+        check_round_trip(&game);
+    }
+
+    #[test]
+    fn test_saves_are_smaller_than_the_states() {
+        let mut game = game_with(ChessState::new());
+        game.move_piece(1, 4, 3, 4);
+        game.move_piece(6, 4, 4, 4);
+        game.move_piece(0, 6, 2, 5);
+        game.move_piece(7, 1, 5, 2);
+        let states = serde_json::to_string(&game.chess_state_history).unwrap();
+        assert!(game.to_save().unwrap().len() * 2 < states.len());
+    }
+
+    #[test]
+    fn test_change_the_steps_dont_make_is_kept_whole() {
+        let mut game = game_with(ChessState::new());
+        game.move_piece(1, 4, 3, 4);
+        let mut chess_state = game.get_current_state().unwrap();
+        chess_state.chessboard.board[0][0].pokemon_type = PokemonType::Dragon;
+        game.chess_state_history.add_state(chess_state);
+        game.move_piece(6, 4, 4, 4);
+        let steps = steps(&game);
+        assert_eq!(
+            steps.iter().map(is_snapshot).collect::<Vec<bool>>(),
+            vec![false, true, false]
+        );
+        assert_eq!(Game::from_save(&game.to_save().unwrap()).unwrap(), game);
+    }
+
+    #[test]
+    fn test_game_without_states_is_not_saved() {
+        let game = Game::new("test".to_string(), ChessStateHistory::new());
+        assert!(game.to_save().is_err());
+    }
+}
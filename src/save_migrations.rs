@@ -0,0 +1,221 @@
+// Saves start with a format number, and older saves are brought up to the current format
+// one format at a time before save_format.rs reads them. The migrations work on the JSON,
+// so they don't depend on what the structs look like now.
+//
+// A change to the saved structs that older saves can't be read with, e.g. a renamed field
+// or one without a default, bumps CURRENT_FORMAT and adds the migration from the format
+// before it to MIGRATIONS, along with a fixture saved in the old format.
+//
+// Formats
+// 1: the whole Game with a ChessState per ply, no format field
+// 2: the first state and the steps played from it, the rules only kept with the states
+
+use serde_json::{json, Map, Value};
+
+pub const CURRENT_FORMAT: u32 = 2;
+
+type Migration = fn(Map<String, Value>) -> Result<Map<String, Value>, String>;
+
+// MIGRATIONS[n] takes a save in format n + 1 to format n + 2
+const MIGRATIONS: [Migration; CURRENT_FORMAT as usize - 1] = [steps_from_states];
+
+// saves from before there was a format field are format 1
+pub fn format_of(save: &Value) -> Result<u32, String> {
+    return match save.get("format") {
+        None => Ok(1),
+        Some(format) => format
+            .as_u64()
+            .and_then(|format| u32::try_from(format).ok())
+            .ok_or(format!("{} isn't a save format", format)),
+    };
+}
+
+/**
+ * Brings a save in any format up to CURRENT_FORMAT. Saves from a newer server
+ * are turned down rather than read wrong
+ */
+pub fn migrate(save: Value) -> Result<Value, String> {
+    let format = format_of(&save)?;
+    if format == 0 || format > CURRENT_FORMAT {
+        return Err(format!(
+            "Save format {} isn't one this server can read, it reads up to {}",
+            format, CURRENT_FORMAT
+        ));
+    }
+    let mut save = match save {
+        Value::Object(save) => save,
+        _ => return Err("A save has to be a JSON object".to_string()),
+    };
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(format as usize - 1) {
+        let to_format = index as u32 + 2;
+        save = migration(save).map_err(|err| {
+            format!(
+                "Failed to bring the save up to format {}: {}",
+                to_format, err
+            )
+        })?;
+        save.insert("format".to_string(), json!(to_format));
+    }
+    return Ok(Value::Object(save));
+}
+
+/**
+ * 1 to 2. The first state is the start, the ones after it are kept whole as snapshot
+ * steps, the next save of the game works out the moves between them
+ */
+fn steps_from_states(mut save: Map<String, Value>) -> Result<Map<String, Value>, String> {
+    let history = match save.remove("chess_state_history") {
+        Some(Value::Object(history)) => history,
+        _ => return Err("There is no chess_state_history".to_string()),
+    };
+    let mut states = match history.get("state_history") {
+        Some(Value::Array(states)) if !states.is_empty() => states.clone(),
+        _ => return Err("There are no states".to_string()),
+    };
+    let current_state_index = history
+        .get("current_state_index")
+        .cloned()
+        .ok_or("There is no current_state_index")?;
+    // the rules were kept with the game as well as the states, states from before they
+    // were kept with the states get the game's
+    if let Some(settings) = save.remove("settings") {
+        for state in states.iter_mut().filter_map(Value::as_object_mut) {
+            state.entry("settings").or_insert_with(|| settings.clone());
+        }
+    }
+    let start = states.remove(0);
+    let steps: Vec<Value> = states
+        .into_iter()
+        .map(|state| json!({ "action": { "Snapshot": state } }))
+        .collect();
+    save.insert("start".to_string(), start);
+    save.insert("steps".to_string(), Value::Array(steps));
+    save.insert("current_state_index".to_string(), current_state_index);
+    // games saved before saves had a version
+    save.entry("version").or_insert(json!(0));
+    return Ok(save);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_structs::{ChessPieceType, InfoMessage, Player, Winner};
+    use crate::game::Game;
+
+    // saved by the first release, before states had rolls, rules, clocks or a draw offer
+    const FIRST_RELEASE: &str = include_str!("../fixtures/saves/format_1_first_release.pchess");
+    // e4 d5 exd5 Qxd5 Nc3 with clocks, rolls and a seed, black's draw offer after it is undone
+    const FORMAT_1: &str = include_str!("../fixtures/saves/format_1.pchess");
+    // the same game saved in format 2
+    const FORMAT_2: &str = include_str!("../fixtures/saves/format_2.pchess");
+
+    fn migrated_format(save: &str) -> u32 {
+        let save = migrate(serde_json::from_str(save).unwrap()).unwrap();
+        return format_of(&save).unwrap();
+    }
+
+    // loads, and saves again in the current format without changing
+    fn open(save: &str) -> Game {
+        let game = Game::from_save(save).unwrap();
+        let resaved = game.to_save().unwrap();
+        assert_eq!(
+            format_of(&serde_json::from_str(&resaved).unwrap()),
+            Ok(CURRENT_FORMAT)
+        );
+        assert_eq!(Game::from_save(&resaved).unwrap(), game);
+        return game;
+    }
+
+    #[test]
+    fn test_fixtures_are_brought_up_to_date() {
+        for save in [FIRST_RELEASE, FORMAT_1, FORMAT_2] {
+            assert_eq!(migrated_format(save), CURRENT_FORMAT);
+        }
+    }
+
+    #[test]
+    fn test_first_release_save_opens() {
+        let game = open(FIRST_RELEASE);
+        assert_eq!(game.name, "Litten_Aggron");
+        assert!(game.settings().local_play);
+        assert_eq!(game.version, 0);
+        assert_eq!(game.chess_state_history.state_history.len(), 4);
+        assert_eq!(game.chess_state_history.current_state_index, 3);
+
+        let chess_state = game.get_current_state().unwrap();
+        assert_eq!(chess_state.turn_count, 3);
+        assert_eq!(chess_state.player, Player::Black);
+        assert_eq!(chess_state.winner, Winner::NoneYet);
+        assert_eq!(chess_state.clock, None);
+        assert_eq!(chess_state.draft, None);
+        let last_move = chess_state.chessboard.history.last_move().unwrap();
+        assert_eq!((last_move.to_row, last_move.to_col), (4, 3));
+        assert!(last_move.capture.is_some());
+        assert_eq!(
+            chess_state.chessboard.get_piece(4, 3).piece_type,
+            ChessPieceType::WhitePawn
+        );
+
+        // and can be played on
+        let mut game = game;
+        assert!(game.move_piece(7, 3, 4, 3));
+    }
+
+    #[test]
+    fn test_format_1_save_opens() {
+        let game = open(FORMAT_1);
+        assert_eq!(game.name, "Whimsicott_Glimmet");
+        assert!(game.settings().critical_hits && game.settings().misses);
+        assert_eq!(game.settings().seed, Some(20251017));
+        assert_eq!(game.version, 7);
+        let history = &game.chess_state_history;
+        assert_eq!(history.state_history.len(), 7);
+        assert_eq!(history.current_state_index, 5);
+        assert_eq!(history.state_history[6].draw_offer, Some(Player::Black));
+
+        let chess_state = game.get_current_state().unwrap();
+        assert_eq!(chess_state.turn_count, 5);
+        assert_eq!(chess_state.rng_seed, 20251017);
+        assert_eq!(chess_state.draw_offer, None);
+        let clock = chess_state.clock.unwrap();
+        assert_eq!(clock.running, Some(Player::Black));
+        assert_eq!(clock.white_remaining_ms, 303939);
+        // Qxd5 wasn't very effective, the queen and the pawn are both gone
+        assert_eq!(
+            history.state_history[4].info_message,
+            Some(InfoMessage::NotVeryEffective)
+        );
+        assert_eq!(
+            chess_state.chessboard.get_piece(4, 3).piece_type,
+            ChessPieceType::Empty
+        );
+    }
+
+    #[test]
+    fn test_format_2_save_opens() {
+        assert_eq!(open(FORMAT_2), open(FORMAT_1));
+    }
+
+    #[test]
+    fn test_format_1_states_become_snapshot_steps() {
+        let save = migrate(serde_json::from_str(FORMAT_1).unwrap()).unwrap();
+        assert_eq!(save["version"], json!(7));
+        assert_eq!(save["current_state_index"], json!(5));
+        assert_eq!(save["start"]["turn_count"], json!(0));
+        let steps = save["steps"].as_array().unwrap();
+        assert_eq!(steps.len(), 6);
+        assert_eq!(steps[0]["action"]["Snapshot"]["turn_count"], json!(1));
+        assert!(save.get("chess_state_history").is_none());
+    }
+
+    #[test]
+    fn test_saves_that_cant_be_read_are_turned_down() {
+        let newer = json!({ "format": CURRENT_FORMAT + 1 });
+        assert!(migrate(newer).unwrap_err().contains("reads up to"));
+        assert!(migrate(json!({ "format": "two" })).is_err());
+        assert!(migrate(json!({ "format": 0 })).is_err());
+        assert!(migrate(json!([1, 2])).is_err());
+        assert!(migrate(json!({ "name": "no states" })).is_err());
+        assert!(Game::from_save("not json").is_err());
+    }
+}
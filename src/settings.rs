@@ -1,3 +1,4 @@
+use crate::chess_structs::{Player, PokemonType};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -5,6 +6,53 @@ pub struct Settings {
     pub local_play: bool,
     pub critical_hits: bool,
     pub misses: bool,
+    // None plays without clocks
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    // the side the computer plays, None when both players are people
+    #[serde(default)]
+    pub ai_player: Option<Player>,
+    #[serde(default)]
+    pub ai_difficulty: Difficulty,
+    // shuffles the starting types and drives the move rolls, None picks a random one
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub type_assignment: TypeAssignment,
+}
+
+// how the pieces get their types before the first move, see type_assignment.rs
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum TypeAssignment {
+    // every side draws 16 of the 18 types without repeats
+    #[default]
+    Random,
+    // both sides get the same layout, the a1 rook's type is the a8 rook's type
+    Mirrored,
+    AllOneType(PokemonType),
+    // the players pick their pieces' types in turn before move 1
+    Draft,
+    // random, but the layout that gives neither side the better matchups is kept
+    WeightedRandom,
+}
+
+// how strong the AI plays, see Difficulty::search_limits
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+    Expert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeControl {
+    pub base_ms: u64,
+    // added to a player's clock once their turn is over
+    pub increment_ms: u64,
+    // given at the start of every turn before the clock starts counting down
+    pub delay_ms: Option<u64>,
 }
 
 impl Settings {
@@ -13,10 +61,41 @@ impl Settings {
             local_play,
             critical_hits,
             misses,
+            time_control: None,
+            ai_player: None,
+            ai_difficulty: Difficulty::default(),
+            seed: None,
+            type_assignment: TypeAssignment::default(),
         }
     }
+}
 
-    pub fn default() -> Self {
+// Games saved before a setting existed load with these values
+impl Default for Settings {
+    fn default() -> Self {
         Settings::new(false, false, false)
     }
 }
+
+impl TimeControl {
+    pub fn new(base_ms: u64, increment_ms: u64, delay_ms: Option<u64>) -> Self {
+        TimeControl {
+            base_ms,
+            increment_ms,
+            delay_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_difficulty_default_when_missing_from_save() {
+        let mut json = serde_json::to_value(Settings::default()).unwrap();
+        json.as_object_mut().unwrap().remove("ai_difficulty");
+        let settings: Settings = serde_json::from_value(json).unwrap();
+        assert_eq!(settings.ai_difficulty, Difficulty::Medium);
+    }
+}
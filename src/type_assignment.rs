@@ -0,0 +1,305 @@
+// The ways pieces get their types before the first move, picked with Settings::type_assignment.
+// Types are dealt as 16 per side, in the order ChessBoard::create_board_with_types hands them out,
+// and every mode except Draft is dealt up front from the game's seed.
+
+use crate::ai::piece_value;
+use crate::chess_structs::{
+    ChessBoard, ChessPieceType, ChessState, Draft, InteractionType, Piece, Player, PokemonType,
+    BOARD_SIZE,
+};
+use crate::settings::TypeAssignment;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+pub const PIECES_PER_SIDE: usize = 2 * BOARD_SIZE;
+// how many random layouts WeightedRandom picks the fairest from
+const WEIGHTED_CANDIDATES: usize = 64;
+
+impl TypeAssignment {
+    /**
+     * The types for white's and black's pieces. Draft deals NoType to every piece,
+     * the players fill them in with ChessState::draft_type
+     */
+    pub fn deal(&self, rng: &mut impl Rng) -> (Vec<PokemonType>, Vec<PokemonType>) {
+        return match self {
+            TypeAssignment::Random => (shuffled_types(rng), shuffled_types(rng)),
+            TypeAssignment::Mirrored => {
+                let types = shuffled_types(rng);
+                (types.clone(), types)
+            }
+            TypeAssignment::AllOneType(pokemon_type) => (
+                vec![*pokemon_type; PIECES_PER_SIDE],
+                vec![*pokemon_type; PIECES_PER_SIDE],
+            ),
+            TypeAssignment::Draft => (
+                vec![PokemonType::NoType; PIECES_PER_SIDE],
+                vec![PokemonType::NoType; PIECES_PER_SIDE],
+            ),
+            TypeAssignment::WeightedRandom => (0..WEIGHTED_CANDIDATES)
+                .map(|_| (shuffled_types(rng), shuffled_types(rng)))
+                .min_by_key(|(white_types, black_types)| {
+                    let board = ChessBoard::create_board_with_types(
+                        white_types.clone(),
+                        black_types.clone(),
+                    );
+                    matchup_advantage(&board).abs()
+                })
+                .unwrap(),
+        };
+    }
+
+    // the names /start takes, AllOneType needs the type to go with it
+    pub fn from_name(name: &str, pokemon_type: Option<PokemonType>) -> Result<Self, String> {
+        return match (name, pokemon_type) {
+            ("Random", _) => Ok(TypeAssignment::Random),
+            ("Mirrored", _) => Ok(TypeAssignment::Mirrored),
+            ("AllOneType", Some(PokemonType::NoType)) | ("AllOneType", None) => {
+                Err("AllOneType needs a type for the pieces".to_string())
+            }
+            ("AllOneType", Some(pokemon_type)) => Ok(TypeAssignment::AllOneType(pokemon_type)),
+            ("Draft", _) => Ok(TypeAssignment::Draft),
+            ("WeightedRandom", _) => Ok(TypeAssignment::WeightedRandom),
+            _ => Err(format!("{} isn't a type assignment", name)),
+        };
+    }
+}
+
+impl Draft {
+    // both players pick from every type, each type at most once
+    pub fn new() -> Self {
+        Draft {
+            white_types: PokemonType::ALL.to_vec(),
+            black_types: PokemonType::ALL.to_vec(),
+        }
+    }
+
+    pub fn types_left(&self, player: Player) -> &Vec<PokemonType> {
+        return match player {
+            Player::White => &self.white_types,
+            Player::Black => &self.black_types,
+        };
+    }
+
+    fn types_left_mut(&mut self, player: Player) -> &mut Vec<PokemonType> {
+        return match player {
+            Player::White => &mut self.white_types,
+            Player::Black => &mut self.black_types,
+        };
+    }
+}
+
+impl Default for Draft {
+    fn default() -> Self {
+        Draft::new()
+    }
+}
+
+/**
+ * Draft rules
+ * - the player to move picks a type for one of their pieces that doesn't have one yet,
+ *   from the types they haven't used
+ * - the players take turns, once a side has typed all of its pieces the other picks the rest
+ * - white moves first once every piece has a type
+ */
+impl ChessState {
+    pub fn is_drafting(&self) -> bool {
+        return self.draft.is_some();
+    }
+
+    pub fn draft_type(
+        &mut self,
+        row: usize,
+        col: usize,
+        pokemon_type: PokemonType,
+    ) -> Result<(), String> {
+        let player = self.player;
+        let draft = self.draft.as_mut().ok_or("The draft is over".to_string())?;
+        if row >= BOARD_SIZE || col >= BOARD_SIZE {
+            return Err("That square isn't on the board".to_string());
+        }
+        let piece = &mut self.chessboard.board[row][col];
+        if piece.piece_type == ChessPieceType::Empty
+            || piece.piece_type.get_piece_player() != player
+            || piece.pokemon_type != PokemonType::NoType
+        {
+            return Err("That piece can't be given a type".to_string());
+        }
+        let types_left = draft.types_left_mut(player);
+        let index = types_left
+            .iter()
+            .position(|left| *left == pokemon_type)
+            .ok_or(format!("{} has already been picked", pokemon_type))?;
+        types_left.remove(index);
+        piece.pokemon_type = pokemon_type;
+
+        if self.undrafted_pieces(player.other_player()) > 0 {
+            self.player = player.other_player();
+        } else if self.undrafted_pieces(player) == 0 {
+            self.draft = None;
+            self.player = Player::White;
+            self.position_history = vec![self.chessboard.position_key(self.player)];
+        }
+        return Ok(());
+    }
+
+    // the pieces the player still has to pick a type for
+    pub fn undrafted_pieces(&self, player: Player) -> usize {
+        return self
+            .chessboard
+            .board
+            .iter()
+            .flatten()
+            .filter(|piece| {
+                piece.piece_type != ChessPieceType::Empty
+                    && piece.piece_type.get_piece_player() == player
+                    && piece.pokemon_type == PokemonType::NoType
+            })
+            .count();
+    }
+}
+
+fn shuffled_types(rng: &mut impl Rng) -> Vec<PokemonType> {
+    let mut types = PokemonType::ALL.to_vec();
+    types.shuffle(rng);
+    return types;
+}
+
+/**
+ * How much better white's types do against black's than the other way around,
+ * every piece against every other piece, weighted by the value of the piece on the
+ * receiving end. Zero is an even matchup
+ */
+pub fn matchup_advantage(board: &[[Piece; BOARD_SIZE]; BOARD_SIZE]) -> i32 {
+    let pieces: Vec<_> = board
+        .iter()
+        .flatten()
+        .filter(|piece| piece.piece_type != ChessPieceType::Empty)
+        .collect();
+    let mut advantage = 0;
+    for white in pieces
+        .iter()
+        .filter(|piece| piece.piece_type.get_piece_player() == Player::White)
+    {
+        for black in pieces
+            .iter()
+            .filter(|piece| piece.piece_type.get_piece_player() == Player::Black)
+        {
+            let white_attacks = PokemonType::type_matchup(white.pokemon_type, black.pokemon_type);
+            let black_attacks = PokemonType::type_matchup(black.pokemon_type, white.pokemon_type);
+            advantage += piece_value(black.piece_type) * effectiveness(white_attacks)
+                - piece_value(white.piece_type) * effectiveness(black_attacks);
+        }
+    }
+    return advantage;
+}
+
+// damage multipliers from the games, doubled to stay whole
+pub fn effectiveness(interaction: InteractionType) -> i32 {
+    return match interaction {
+        InteractionType::SuperEffective => 4,
+        InteractionType::Normal => 2,
+        InteractionType::NotVeryEffective => 1,
+        InteractionType::NoEffect | InteractionType::Empty => 0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_state_history::ChessStateHistory;
+    use crate::game::Game;
+    use crate::settings::Settings;
+
+    fn dealt(type_assignment: TypeAssignment, seed: u64) -> ChessState {
+        let settings = Settings {
+            seed: Some(seed),
+            type_assignment,
+            ..Settings::default()
+        };
+        return ChessState::new_with_settings(settings);
+    }
+
+    #[test]
+    fn test_mirrored_and_all_one_type() {
+        let board = dealt(TypeAssignment::Mirrored, 3).chessboard.board;
+        let types = |row: usize| board[row].map(|piece| piece.pokemon_type);
+        assert_eq!(types(0), types(7));
+        assert_eq!(types(1), types(6));
+        let board = dealt(TypeAssignment::AllOneType(PokemonType::Ghost), 3)
+            .chessboard
+            .board;
+        assert!(board
+            .iter()
+            .flatten()
+            .filter(|piece| piece.piece_type != ChessPieceType::Empty)
+            .all(|piece| piece.pokemon_type == PokemonType::Ghost));
+        assert!(TypeAssignment::from_name("AllOneType", None).is_err());
+    }
+
+    #[test]
+    fn test_weighted_random_evens_out_the_matchups() {
+        for seed in 0..10 {
+            // the first layout it weighs up is the one Random deals from the same seed
+            let random = dealt(TypeAssignment::Random, seed);
+            let weighted = dealt(TypeAssignment::WeightedRandom, seed);
+            assert!(
+                matchup_advantage(&weighted.chessboard.board).abs()
+                    <= matchup_advantage(&random.chessboard.board).abs()
+            );
+        }
+    }
+
+    #[test]
+    fn test_draft() {
+        let mut chess_state = dealt(TypeAssignment::Draft, 3);
+        assert!(chess_state.is_drafting());
+        assert!(chess_state.all_legal_moves().is_empty());
+        assert!(!chess_state.move_piece(1, 4, 3, 4));
+        // black's pieces aren't white's to pick for
+        assert!(chess_state.draft_type(7, 0, PokemonType::Fire).is_err());
+        chess_state.draft_type(0, 0, PokemonType::Fire).unwrap();
+        assert_eq!(chess_state.player, Player::Black);
+        // each side has its own types to pick from
+        chess_state.draft_type(7, 0, PokemonType::Fire).unwrap();
+        assert!(chess_state.draft_type(0, 1, PokemonType::Fire).is_err());
+        assert!(chess_state.draft_type(0, 0, PokemonType::Water).is_err());
+
+        while chess_state.is_drafting() {
+            let player = chess_state.player;
+            let (row, col) = (0..BOARD_SIZE)
+                .flat_map(|row| (0..BOARD_SIZE).map(move |col| (row, col)))
+                .find(|(row, col)| {
+                    let piece = chess_state.chessboard.get_piece(*row, *col);
+                    piece.piece_type != ChessPieceType::Empty
+                        && piece.piece_type.get_piece_player() == player
+                        && piece.pokemon_type == PokemonType::NoType
+                })
+                .unwrap();
+            let types_left = chess_state.draft.as_ref().unwrap().types_left(player);
+            let pokemon_type = types_left[0];
+            chess_state.draft_type(row, col, pokemon_type).unwrap();
+        }
+        assert_eq!(chess_state.player, Player::White);
+        assert_eq!(chess_state.undrafted_pieces(Player::White), 0);
+        assert_eq!(chess_state.undrafted_pieces(Player::Black), 0);
+        assert!(chess_state.move_piece(1, 4, 3, 4));
+    }
+
+    #[test]
+    fn test_ai_drafts_its_pieces() {
+        let settings = Settings {
+            seed: Some(3),
+            type_assignment: TypeAssignment::Draft,
+            ai_player: Some(Player::Black),
+            ..Settings::default()
+        };
+        let chess_state = ChessState::new_with_settings(settings);
+        let chess_state_history = ChessStateHistory::new_with_initial_state(chess_state);
+        let mut game = Game::new("test".to_string(), chess_state_history);
+        game.draft_type(0, 0, PokemonType::Fire).unwrap();
+        assert!(game.play_ai_turns());
+        let chess_state = game.get_current_state().unwrap();
+        assert_eq!(chess_state.player, Player::White);
+        assert_eq!(chess_state.undrafted_pieces(Player::Black), 15);
+    }
+}
@@ -1,12 +1,14 @@
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::response::Response;
 use axum::{Extension, debug_handler};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use crate::ai::{Hint, DEFAULT_HINT_COUNT};
 use crate::game::Game;
-use crate::chess_structs::{ChessState, Move};
-use crate::app_state::AppState;
+use crate::chess_structs::{ChessState, Move, Player, PokemonType, Winner};
+use crate::app_state::{AppState, Seat};
 use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Mutex, MutexGuard, Notify};
 use futures_util::{sink::SinkExt, stream::{StreamExt, SplitSink, SplitStream}};
 
 
@@ -14,13 +16,60 @@ use futures_util::{sink::SinkExt, stream::{StreamExt, SplitSink, SplitStream}};
 #[derive(Deserialize)]
 #[serde(tag = "action", content = "payload")]
 enum ClientMessage {
+    JoinGame(JoinGamePayload),
     SubscribeToGame(GetGamePayload),
     GetMoves(GetMovesPayload),
     MovePiece(MovePiecePayload),
+    MoveByNotation(MoveByNotationPayload),
+    DraftType(DraftTypePayload),
     SelectPawnPromotionPiece(SelectPawnPromotionPiecePayload),
     GetPreviousState(GetGamePayload),
     GetNextState(GetGamePayload),
     GetCurrentState(GetGamePayload),
+    Resign(GetGamePayload),
+    OfferDraw(GetGamePayload),
+    AcceptDraw(GetGamePayload),
+    DeclineDraw(GetGamePayload),
+    GetHint(GetHintPayload),
+}
+
+// Every message can carry a request id, it's echoed back on the replies it causes
+#[derive(Deserialize)]
+struct ClientRequest {
+    #[serde(default)]
+    request_id: Option<u64>,
+    #[serde(flatten)]
+    message: ClientMessage,
+}
+
+impl ClientMessage {
+    // anything a spectator isn't allowed to send
+    fn changes_game(&self) -> bool {
+        matches!(
+            self,
+            ClientMessage::MovePiece(_)
+                | ClientMessage::MoveByNotation(_)
+                | ClientMessage::DraftType(_)
+                | ClientMessage::SelectPawnPromotionPiece(_)
+                | ClientMessage::GetPreviousState(_)
+                | ClientMessage::GetNextState(_)
+                | ClientMessage::Resign(_)
+                | ClientMessage::OfferDraw(_)
+                | ClientMessage::AcceptDraw(_)
+                | ClientMessage::DeclineDraw(_)
+        )
+    }
+
+    // the human's move is over, so an AI seat may have to reply
+    fn finishes_move(&self) -> bool {
+        matches!(
+            self,
+            ClientMessage::MovePiece(_)
+                | ClientMessage::MoveByNotation(_)
+                | ClientMessage::DraftType(_)
+                | ClientMessage::SelectPawnPromotionPiece(_)
+        )
+    }
 }
 
 #[derive(Deserialize)]
@@ -39,17 +88,65 @@ struct MovePiecePayload {
     to_col: usize,
 }
 
+#[derive(Deserialize)]
+struct MoveByNotationPayload {
+    name: String,
+    // SAN or UCI, Nxe5 or e7e8q
+    notation: String,
+}
+
+#[derive(Deserialize)]
+struct DraftTypePayload {
+    name: String,
+    row: usize,
+    col: usize,
+    pokemon_type: PokemonType,
+}
+
 #[derive(Deserialize)]
 struct SelectPawnPromotionPiecePayload {
     name: String,
     piece_str: String,
 }
 
+#[derive(Deserialize)]
+struct GetHintPayload {
+    name: String,
+    // how many candidate moves to send back
+    count: Option<usize>,
+}
+
 #[derive(Deserialize)]
 struct GetGamePayload {
     name: String,
 }
 
+#[derive(Deserialize)]
+struct JoinGamePayload {
+    name: String,
+    // the token handed out on a previous join, to take the same seat back
+    reconnect_token: Option<String>,
+    // watch the game without taking a free seat
+    #[serde(default)]
+    spectate: bool,
+}
+
+// Who is on the other end of a socket, settled by the join handshake
+struct Connection {
+    room_name: String,
+    seat: Seat,
+    // replies meant only for this client, everything for the room goes through the room's tx
+    reply_tx: mpsc::UnboundedSender<String>,
+    game_lock: Arc<Mutex<()>>,
+}
+
+// Who gets the response to a request
+#[derive(PartialEq)]
+enum Audience {
+    Requester,
+    Room,
+}
+
 #[derive(Serialize)]
 #[serde(tag = "status", content = "data")]
 enum ServerMessage {
@@ -57,11 +154,36 @@ enum ServerMessage {
     Error { message: String },
 }
 
+#[derive(Serialize)]
+struct ServerResponse {
+    // the request this answers, None for messages the server pushes on its own
+    request_id: Option<u64>,
+    #[serde(flatten)]
+    message: ServerMessage,
+}
+
+impl ServerMessage {
+    fn into_response_string(self, request_id: Option<u64>) -> String {
+        serde_json::to_string(&ServerResponse { request_id, message: self }).unwrap()
+    }
+}
+
 #[derive(Serialize)]
 #[serde(untagged)]
 enum ServerMessageData {
     Moves { moves: Vec<Move> },
-    ChessState { chess_state: ChessState },
+    ChessState {
+        #[serde(serialize_with = "serialize_for_players")]
+        chess_state: ChessState,
+    },
+    Joined { seat: Seat, reconnect_token: String },
+    SpectatorCount { spectators: usize },
+    Hints { hints: Vec<Hint> },
+}
+
+// every state goes out without what only the server may know, see ChessState::for_players
+fn serialize_for_players<S: Serializer>(chess_state: &ChessState, serializer: S) -> Result<S::Ok, S::Error> {
+    chess_state.for_players().serialize(serializer)
 }
 
 #[debug_handler]
@@ -76,29 +198,66 @@ pub async fn handle_socket(socket: WebSocket, app_state: Arc<Mutex<AppState>>) {
     // we want to put the sender into our AppState so that when
     // a move is made, we can send it to everyone in the room
     let (sender, mut receiver) = socket.split();
-    let room_connection = wait_for_room_connection(&mut receiver, app_state.clone()).await;
+    let (reply_tx, reply_rx) = mpsc::unbounded_channel();
+    let room_connection = wait_for_room_connection(&mut receiver, reply_tx, app_state.clone()).await;
     if room_connection.is_none() {
         return;
     }
-    let (room_name, room_tx) = room_connection.unwrap();
+    let (connection, request_id, reconnect_token, room_tx) = room_connection.unwrap();
     let user_rx = room_tx.subscribe();
-    
-    // listen on the user_rx and send any messages to the client
-    tokio::spawn(handle_sender(sender, user_rx));
 
-    tokio::spawn(handle_reciever(receiver, app_state.clone()));
-    // send the initial board state
-    let chess_state = Game::load(&room_name).await.get_current_state().unwrap();
-    let _ = room_tx.send(serde_json::to_string(&ServerMessage::Success(ServerMessageData::ChessState { chess_state })).unwrap());
+    // the reconnect token only goes to this client, anyone holding it can take the seat
+    let joined = ServerMessage::Success(ServerMessageData::Joined { seat: connection.seat, reconnect_token });
+    let _ = connection.reply_tx.send(joined.into_response_string(request_id));
+    // followed by the board the client joined to
+    let chess_state = Game::load(&connection.room_name).await.get_current_state().unwrap();
+    let current_state = ServerMessage::Success(ServerMessageData::ChessState { chess_state });
+    let _ = connection.reply_tx.send(current_state.into_response_string(request_id));
+    let room_name = connection.room_name.clone();
+
+    // listen on the user_rx and the reply channel and send any messages to the client
+    tokio::spawn(handle_sender(sender, user_rx, reply_rx));
+
+    tokio::spawn(handle_reciever(receiver, connection, app_state.clone()));
+    send_spectator_count(&room_name, app_state).await;
+}
+
+// let everyone in the room know how many people are watching
+async fn send_spectator_count(room_name: &str, app_state: Arc<Mutex<AppState>>) {
+    let mut app_state = app_state.lock().await;
+    let spectators = app_state.spectator_count(room_name);
+    let room_tx = app_state.get_room_tx(room_name);
+    let _ = room_tx.send(ServerMessage::Success(ServerMessageData::SpectatorCount { spectators }).into_response_string(None));
 }
 
-async fn handle_sender(mut sender: SplitSink<WebSocket, Message>, mut user_rx: broadcast::Receiver<String> ) {
-    while let Ok(msg) = user_rx.recv().await {
-        let _ = sender.send(Message::Text(msg)).await;
+async fn handle_sender(mut sender: SplitSink<WebSocket, Message>, mut user_rx: broadcast::Receiver<String>, mut reply_rx: mpsc::UnboundedReceiver<String>) {
+    loop {
+        let msg = tokio::select! {
+            // answer the client's own requests first
+            biased;
+            msg = reply_rx.recv() => match msg {
+                Some(msg) => msg,
+                // the reciever is done, so is the client
+                None => return,
+            },
+            msg = user_rx.recv() => match msg {
+                Ok(msg) => msg,
+                // we fell behind the room, the next state update will catch the client up
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+        };
+        if sender.send(Message::Text(msg)).await.is_err() {
+            return;
+        }
     }
 }
 
-async fn wait_for_room_connection(receiver: &mut SplitStream<WebSocket>, app_state: Arc<Mutex<AppState>>) -> Option<(String, broadcast::Sender<String>)> {
+/**
+ * The first message on a socket has to be a JoinGame (or a SubscribeToGame from older clients,
+ * which joins without a reconnect token). Anything else is ignored until the client joins
+ */
+async fn wait_for_room_connection(receiver: &mut SplitStream<WebSocket>, reply_tx: mpsc::UnboundedSender<String>, app_state: Arc<Mutex<AppState>>) -> Option<(Connection, Option<u64>, String, broadcast::Sender<String>)> {
     while let Some(msg) = receiver.next().await {
         let msg = if let Ok(msg) = msg {
             msg
@@ -107,83 +266,273 @@ async fn wait_for_room_connection(receiver: &mut SplitStream<WebSocket>, app_sta
             return None;
         };
         if let Message::Text(text) = msg {
-            match parse_client_message(text.clone()).await {
-                Ok((game_name, _client_msg)) => {
-                    // pull the room_tx out of the app_state
-                    let room_tx = app_state.lock().await.get_room_tx(&game_name);
-                    return Some((game_name, room_tx));
-                },
-                Err(error_msg) => {continue;}
+            let (game_name, request) = match parse_client_message(text).await {
+                Ok(parsed) => parsed,
+                Err(_) => continue,
+            };
+            let (reconnect_token, spectate) = match request.message {
+                ClientMessage::JoinGame(payload) => (payload.reconnect_token, payload.spectate),
+                ClientMessage::SubscribeToGame(_) => (None, false),
+                _ => continue,
+            };
+            let settings = Game::load(&game_name).await.settings();
+            // pull the room_tx out of the app_state and take a seat in the room
+            let app_state_handle = app_state.clone();
+            let mut app_state = app_state.lock().await;
+            match settings.ai_player {
+                Some(Player::White) => app_state.reserve_seat(&game_name, Seat::White),
+                Some(Player::Black) => app_state.reserve_seat(&game_name, Seat::Black),
+                None => {}
             }
-
+            let (seat, reconnect_token) = app_state.join_room(&game_name, reconnect_token, spectate);
+            let room_tx = app_state.get_room_tx(&game_name);
+            let game_lock = app_state.game_lock(&game_name);
+            // only timed games need someone watching the clock
+            if settings.time_control.is_some() {
+                if let Some(clock_changed) = app_state.start_clock_timer(&game_name) {
+                    tokio::spawn(run_clock_timer(game_name.clone(), clock_changed, room_tx.clone(), game_lock.clone(), app_state_handle.clone()));
+                }
+            }
+            let connection = Connection { room_name: game_name, seat, reply_tx, game_lock };
+            return Some((connection, request.request_id, reconnect_token, room_tx));
         }
     }
     return None
 }
 
-async fn handle_reciever(mut receiver: SplitStream<WebSocket>, app_state: Arc<Mutex<AppState>>){
+async fn handle_reciever(mut receiver: SplitStream<WebSocket>, connection: Connection, app_state: Arc<Mutex<AppState>>){
     while let Some(msg) = receiver.next().await {
         let msg = if let Ok(msg) = msg {
             msg
         } else {
             // client disconnected
-            return;
+            break;
         };
         if let Message::Text(text) = msg {
-            let (room_name, response) = handle_message(text, app_state.clone()).await;
-            // send the response to everyone in the room
-            // need state to do that
-            let room_tx = app_state.lock().await.get_room_tx(&room_name);
+            let (request_id, response, audience, ai_may_reply, game_guard) = handle_message(text, &connection).await;
             // have to send as string not message
-            let _ = room_tx.send(serde_json::to_string(&response).unwrap());
+            let response = response.into_response_string(request_id);
+            if audience == Audience::Room {
+                // send the response to everyone in the room
+                // need state to do that
+                let mut app_state = app_state.lock().await;
+                let room_tx = app_state.get_room_tx(&connection.room_name);
+                let _ = room_tx.send(response);
+                // the move may have switched clocks, or ended the game
+                app_state.notify_clock_changed(&connection.room_name);
+            } else {
+                let _ = connection.reply_tx.send(response);
+            }
+            // the room has this change before the next one can be made
+            drop(game_guard);
+            if ai_may_reply {
+                play_ai_reply(&connection.room_name, app_state.clone()).await;
+            }
         }
     }
+    // the client is gone, free up its place in the room
+    app_state.lock().await.leave_room(&connection.room_name, connection.seat);
+    if connection.seat == Seat::Spectator {
+        send_spectator_count(&connection.room_name, app_state).await;
+    }
+}
+
+/**
+ * Lets the AI seat answer a human move. The search runs on the blocking pool so it doesn't
+ * hold up other rooms, and every state it plays goes out to the room
+ */
+async fn play_ai_reply(room_name: &str, app_state: Arc<Mutex<AppState>>) {
+    let game_lock = app_state.lock().await.game_lock(room_name);
+    let game_guard = game_lock.lock().await;
+    let mut game = Game::load(&room_name.to_string()).await;
+    let mut game = match tokio::task::spawn_blocking(move || game.play_ai_turns().then_some(game)).await {
+        Ok(Some(game)) => game,
+        // not the AI's turn, or no AI in this game
+        _ => return,
+    };
+    // someone else's change got in first, theirs is the one the room has
+    if !game.save().await {
+        return;
+    }
+    let chess_state = game.get_current_state().unwrap();
+    let response = ServerMessage::Success(ServerMessageData::ChessState { chess_state });
+    let mut app_state = app_state.lock().await;
+    let room_tx = app_state.get_room_tx(room_name);
+    let _ = room_tx.send(response.into_response_string(None));
+    app_state.notify_clock_changed(room_name);
+    // held until the room has the reply, so a move made after it goes out after it
+    drop(game_guard);
+}
 
+/**
+ * Flags the player to move when their clock runs out, even if nobody sends another message.
+ * Sleeps until the running clock would hit zero and starts over whenever a move changes the clock,
+ * stops once the game is over or everyone has left the room
+ */
+async fn run_clock_timer(room_name: String, clock_changed: Arc<Notify>, room_tx: broadcast::Sender<String>, game_lock: Arc<Mutex<()>>, app_state: Arc<Mutex<AppState>>) {
+    loop {
+        let game = Game::load(&room_name).await;
+        let game_over = game.get_current_state().is_none_or(|chess_state| chess_state.winner != Winner::NoneYet);
+        if app_state.lock().await.clock_timer_done(&room_name, game_over) {
+            return;
+        }
+        let time_until_flag_ms = game.time_until_flag_ms();
+        let time_until_flag_ms = match time_until_flag_ms {
+            Some(time_until_flag_ms) => time_until_flag_ms,
+            None => {
+                // no clock running, wait for a move to start one
+                clock_changed.notified().await;
+                continue;
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(time_until_flag_ms)) => {}
+            _ = clock_changed.notified() => continue,
+        }
+        let _game_guard = game_lock.lock().await;
+        let mut game = Game::load(&room_name).await;
+        if game.flag_if_out_of_time() && game.save().await {
+            let chess_state = game.get_current_state().unwrap();
+            let response = ServerMessage::Success(ServerMessageData::ChessState { chess_state });
+            let _ = room_tx.send(response.into_response_string(None));
+        }
+    }
 }
 
-async fn parse_client_message(message: String) -> Result<(String, ClientMessage), String> {
-    match serde_json::from_str::<ClientMessage>(&message) {
-        Ok(msg) => {
-            let game_name = match &msg {
+async fn parse_client_message(message: String) -> Result<(String, ClientRequest), String> {
+    match serde_json::from_str::<ClientRequest>(&message) {
+        Ok(request) => {
+            let game_name = match &request.message {
+                ClientMessage::JoinGame(payload) => payload.name.clone(),
                 ClientMessage::SubscribeToGame(payload) => payload.name.clone(),
                 ClientMessage::GetMoves(payload) => payload.name.clone(),
                 ClientMessage::MovePiece(payload) => payload.name.clone(),
+                ClientMessage::MoveByNotation(payload) => payload.name.clone(),
+                ClientMessage::DraftType(payload) => payload.name.clone(),
                 ClientMessage::SelectPawnPromotionPiece(payload) => payload.name.clone(),
                 ClientMessage::GetPreviousState(payload) => payload.name.clone(),
                 ClientMessage::GetNextState(payload) => payload.name.clone(),
                 ClientMessage::GetCurrentState(payload) => payload.name.clone(),
+                ClientMessage::Resign(payload) => payload.name.clone(),
+                ClientMessage::OfferDraw(payload) => payload.name.clone(),
+                ClientMessage::AcceptDraw(payload) => payload.name.clone(),
+                ClientMessage::DeclineDraw(payload) => payload.name.clone(),
+                ClientMessage::GetHint(payload) => payload.name.clone(),
             };
-            Ok((game_name, msg))
+            Ok((game_name, request))
         },
         Err(_) => Err("Invalid message format".to_string()),
     }
 }
 
-async fn handle_client_action(msg: ClientMessage, app_state: Arc<Mutex<AppState>>) -> ServerMessage {
+async fn handle_client_action(msg: ClientMessage, connection: &Connection) -> ServerMessage {
     match msg {
-        ClientMessage::SubscribeToGame(payload) => subscribe_to_game(payload, app_state).await,
+        ClientMessage::JoinGame(payload) => get_current_state(GetGamePayload { name: payload.name }).await,
+        ClientMessage::SubscribeToGame(payload) => subscribe_to_game(payload).await,
         ClientMessage::GetMoves(payload) => get_moves(payload).await,
-        ClientMessage::MovePiece(payload) => move_piece(payload).await,
-        ClientMessage::SelectPawnPromotionPiece(payload) => select_pawn_promotion_piece(payload).await,
-        ClientMessage::GetPreviousState(payload) => get_previous_state(payload).await,
-        ClientMessage::GetNextState(payload) => get_next_state(payload).await,
+        ClientMessage::MovePiece(payload) => move_piece(payload, connection.seat).await,
+        ClientMessage::MoveByNotation(payload) => move_by_notation(payload, connection.seat).await,
+        ClientMessage::DraftType(payload) => draft_type(payload, connection.seat).await,
+        ClientMessage::SelectPawnPromotionPiece(payload) => select_pawn_promotion_piece(payload, connection.seat).await,
+        ClientMessage::GetPreviousState(payload) => get_previous_state(payload, connection.seat).await,
+        ClientMessage::GetNextState(payload) => get_next_state(payload, connection.seat).await,
         ClientMessage::GetCurrentState(payload) => get_current_state(payload).await,
+        ClientMessage::Resign(payload) => end_of_game_action(payload, connection.seat, seat_player, Game::resign).await,
+        ClientMessage::OfferDraw(payload) => end_of_game_action(payload, connection.seat, seat_player, Game::offer_draw).await,
+        ClientMessage::AcceptDraw(payload) => end_of_game_action(payload, connection.seat, draw_answerer, Game::accept_draw).await,
+        ClientMessage::DeclineDraw(payload) => end_of_game_action(payload, connection.seat, draw_answerer, Game::decline_draw).await,
+        ClientMessage::GetHint(payload) => get_hint(payload).await,
     }
 }
 
-async fn handle_message(message: String, app_state: Arc<Mutex<AppState>>) -> (String, ServerMessage) {
-    match parse_client_message(message).await {
-        Ok((game_name, client_msg)) => {
-            let server_msg = handle_client_action(client_msg, app_state).await;
-            (game_name, server_msg)
-        },
-        Err(error_msg) => ("".to_string(), ServerMessage::Error { message: error_msg }),
+/**
+ * Queries and errors only go back to the client that asked,
+ * changes to the game go out to the whole room. A change comes back with the game's lock
+ * still held, it's let go once the room has been sent the change
+ */
+async fn handle_message<'a>(message: String, connection: &'a Connection) -> (Option<u64>, ServerMessage, Audience, bool, Option<MutexGuard<'a, ()>>) {
+    let (game_name, request) = match parse_client_message(message).await {
+        Ok(parsed) => parsed,
+        Err(error_msg) => return (None, ServerMessage::Error { message: error_msg }, Audience::Requester, false, None),
+    };
+    let request_id = request.request_id;
+    let client_msg = request.message;
+    // a connection is only seated in the room it joined
+    if game_name != connection.room_name {
+        return (request_id, ServerMessage::Error { message: "Not joined to this game".to_string() }, Audience::Requester, false, None);
+    }
+    let changes_game = client_msg.changes_game();
+    if connection.seat == Seat::Spectator && changes_game {
+        return (request_id, ServerMessage::Error { message: "Spectators can't change the game".to_string() }, Audience::Requester, false, None);
     }
+    let finishes_move = client_msg.finishes_move();
+    // changes are made one at a time, a second one waits to load the first one's save
+    let game_guard = if changes_game {
+        Some(connection.game_lock.lock().await)
+    } else {
+        None
+    };
+    let response = handle_client_action(client_msg, connection).await;
+    let audience = match response {
+        ServerMessage::Success(_) if changes_game => Audience::Room,
+        _ => Audience::Requester,
+    };
+    let ai_may_reply = finishes_move && audience == Audience::Room;
+    (request_id, response, audience, ai_may_reply, game_guard)
 }
 
-async fn subscribe_to_game(payload: GetGamePayload, app_state: Arc<Mutex<AppState>>) -> ServerMessage {
+fn wrong_seat_error() -> ServerMessage {
+    ServerMessage::Error { message: "Your seat can't play for this side".to_string() }
+}
+
+// the changed game goes out once it's saved, a save that's turned down changed nothing
+async fn save_and_send_state(game: &mut Game) -> ServerMessage {
+    if !game.save().await {
+        return ServerMessage::Error { message: "Failed to save the game, load it again and retry".to_string() };
+    }
+    ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() })
+}
+
+fn seat_can_play_for(game: &Game, seat: Seat, player: Player) -> bool {
+    seat.can_play_for(player, game.settings().local_play)
+}
+
+// the side a seat speaks for, in local play that's whoever is to move
+fn seat_player(game: &Game, seat: Seat) -> Option<Player> {
+    match seat {
+        Seat::Spectator => None,
+        _ if game.settings().local_play => Some(game.get_current_state().unwrap().player),
+        Seat::White => Some(Player::White),
+        Seat::Black => Some(Player::Black),
+    }
+}
+
+// in local play the offer is answered for the side it was made to, whoever is to move
+fn draw_answerer(game: &Game, seat: Seat) -> Option<Player> {
+    let draw_offer = game.get_current_state().unwrap().draw_offer;
+    match (seat, draw_offer) {
+        (Seat::Spectator, _) => None,
+        (_, Some(offered_by)) if game.settings().local_play => Some(offered_by.other_player()),
+        _ => seat_player(game, seat),
+    }
+}
+
+// resigning and answering draw offers, the new state goes out to the room
+// so the other player sees an outstanding offer
+async fn end_of_game_action(payload: GetGamePayload, seat: Seat, player_for: fn(&Game, Seat) -> Option<Player>, action: fn(&mut Game, Player) -> Result<(), String>) -> ServerMessage {
+    let mut game = Game::load(&payload.name).await;
+    let player = match player_for(&game, seat) {
+        Some(player) => player,
+        None => return wrong_seat_error(),
+    };
+    match action(&mut game, player) {
+        Ok(_) => save_and_send_state(&mut game).await,
+        Err(message) => ServerMessage::Error { message },
+    }
+}
+
+async fn subscribe_to_game(payload: GetGamePayload) -> ServerMessage {
     let game = Game::load(&payload.name).await;
-    // app_state.subscribe_to_game(&payload.name);
     ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() })
 }
 
@@ -194,41 +543,98 @@ async fn get_moves(payload: GetMovesPayload) -> ServerMessage {
     ServerMessage::Success(ServerMessageData::Moves { moves: valid_moves })
 }
 
-async fn move_piece(payload: MovePiecePayload) -> ServerMessage {
+async fn get_hint(payload: GetHintPayload) -> ServerMessage {
+    let game = Game::load(&payload.name).await;
+    let count = payload.count.unwrap_or(DEFAULT_HINT_COUNT);
+    // the analysis is too slow to run on the socket's task
+    match tokio::task::spawn_blocking(move || game.get_hints(count)).await {
+        Ok(hints) => ServerMessage::Success(ServerMessageData::Hints { hints }),
+        Err(_) => ServerMessage::Error { message: "Failed to analyse the game".to_string() },
+    }
+}
+
+async fn move_piece(payload: MovePiecePayload, seat: Seat) -> ServerMessage {
     let mut game = Game::load(&payload.name).await;
+    if !seat_can_play_for(&game, seat, game.get_current_state().unwrap().player) {
+        return wrong_seat_error();
+    }
 
     let board_changed = game.move_piece(payload.from_row, payload.from_col, payload.to_row, payload.to_col);
     if board_changed {
-        game.save().await;
+        return save_and_send_state(&mut game).await;
     }
     ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() })
 }
 
-async fn select_pawn_promotion_piece(payload: SelectPawnPromotionPiecePayload) -> ServerMessage {
+async fn move_by_notation(payload: MoveByNotationPayload, seat: Seat) -> ServerMessage {
     let mut game = Game::load(&payload.name).await;
+    if !seat_can_play_for(&game, seat, game.get_current_state().unwrap().player) {
+        return wrong_seat_error();
+    }
+
+    match game.move_by_notation(&payload.notation) {
+        Ok(_) => save_and_send_state(&mut game).await,
+        Err(message) => ServerMessage::Error { message },
+    }
+}
+
+async fn draft_type(payload: DraftTypePayload, seat: Seat) -> ServerMessage {
+    let mut game = Game::load(&payload.name).await;
+    if !seat_can_play_for(&game, seat, game.get_current_state().unwrap().player) {
+        return wrong_seat_error();
+    }
+
+    match game.draft_type(payload.row, payload.col, payload.pokemon_type) {
+        Ok(_) => save_and_send_state(&mut game).await,
+        Err(message) => ServerMessage::Error { message },
+    }
+}
+
+async fn select_pawn_promotion_piece(payload: SelectPawnPromotionPiecePayload, seat: Seat) -> ServerMessage {
+    let mut game = Game::load(&payload.name).await;
+    if !seat_can_play_for(&game, seat, game.get_current_state().unwrap().player) {
+        return wrong_seat_error();
+    }
 
     match game.select_pawn_promotion_piece(payload.piece_str) {
-        Ok(_) => {
-            game.save().await;
-            ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() })
-        },
+        Ok(_) => save_and_send_state(&mut game).await,
         Err(_) => ServerMessage::Error { message: "Invalid pawn promotion piece".to_string() },
     }
 }
 
-async fn get_previous_state(payload: GetGamePayload) -> ServerMessage {
+async fn get_previous_state(payload: GetGamePayload, seat: Seat) -> ServerMessage {
     let mut game = Game::load(&payload.name).await;
+    // against the AI the human takes back the AI's reply along with their own move
+    if let Some(ai_player) = game.settings().ai_player {
+        if !seat_can_play_for(&game, seat, ai_player.other_player()) {
+            return wrong_seat_error();
+        }
+        if game.undo_past_ai_reply(ai_player).is_some() {
+            return save_and_send_state(&mut game).await;
+        }
+        return ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() });
+    }
+    // only the side that made the last move can take it back
+    if let Some(previous_state) = game.chess_state_history.peek_previous_state() {
+        if !seat_can_play_for(&game, seat, previous_state.player) {
+            return wrong_seat_error();
+        }
+    }
     if game.get_previous_state().is_some() {
-        game.save().await;
+        return save_and_send_state(&mut game).await;
     }
     ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() })
 }
 
-async fn get_next_state(payload: GetGamePayload) -> ServerMessage {
+async fn get_next_state(payload: GetGamePayload, seat: Seat) -> ServerMessage {
     let mut game = Game::load(&payload.name).await;
+    // redoing replays the move of the side to play
+    if !seat_can_play_for(&game, seat, game.get_current_state().unwrap().player) {
+        return wrong_seat_error();
+    }
 
     if game.get_next_state().is_some() {
-        game.save().await;
+        return save_and_send_state(&mut game).await;
     }
     ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() })
 }
@@ -237,3 +643,98 @@ async fn get_current_state(payload: GetGamePayload) -> ServerMessage {
     let game = Game::load(&payload.name).await;
     ServerMessage::Success(ServerMessageData::ChessState { chess_state: game.get_current_state().unwrap() })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_state_history::ChessStateHistory;
+    use crate::database::{configure_game_store, MemoryStore};
+    use crate::settings::Settings;
+
+    // a game in a memory store, every test uses its own name
+    async fn saved_game(name: &str, fen: &str, settings: Settings) {
+        // the first test to get here configures the store for all of them
+        let _ = configure_game_store(Box::new(MemoryStore::new()));
+        let chess_state = ChessState::from_fen(fen, settings).unwrap();
+        let mut game = Game::new(name.to_string(), ChessStateHistory::new_with_initial_state(chess_state));
+        game.save_new().await.unwrap();
+    }
+
+    fn connection(name: &str, seat: Seat) -> Connection {
+        let (reply_tx, _) = mpsc::unbounded_channel();
+        Connection { room_name: name.to_string(), seat, reply_tx, game_lock: Arc::new(Mutex::new(())) }
+    }
+
+    // the state a message leaves the game in, or the error it was answered with
+    async fn send(connection: &Connection, action: &str) -> Result<ChessState, String> {
+        let message = format!(r#"{{"action":"{}","payload":{{"name":"{}"}}}}"#, action, connection.room_name);
+        let (_, response, _, _, _) = handle_message(message, connection).await;
+        match response {
+            ServerMessage::Success(ServerMessageData::ChessState { chess_state }) => Ok(chess_state),
+            ServerMessage::Success(_) => Err("Not a chess state".to_string()),
+            ServerMessage::Error { message } => Err(message),
+        }
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[tokio::test]
+    async fn test_local_play_draw_offer_can_be_accepted() {
+        saved_game("draw_local_play", START, Settings::new(true, false, false)).await;
+        let white = connection("draw_local_play", Seat::White);
+        let chess_state = send(&white, "OfferDraw").await.unwrap();
+        assert_eq!(chess_state.draw_offer, Some(Player::White));
+        // answered for black, the side the draw was offered to
+        let chess_state = send(&white, "AcceptDraw").await.unwrap();
+        assert_eq!(chess_state.winner, Winner::Tie);
+
+        saved_game("decline_local_play", START, Settings::new(true, false, false)).await;
+        let black = connection("decline_local_play", Seat::Black);
+        send(&black, "OfferDraw").await.unwrap();
+        let chess_state = send(&black, "DeclineDraw").await.unwrap();
+        assert_eq!((chess_state.draw_offer, chess_state.winner), (None, Winner::NoneYet));
+    }
+
+    #[tokio::test]
+    async fn test_ai_answers_draw_offers() {
+        let settings = Settings { ai_player: Some(Player::Black), ..Settings::new(false, false, false) };
+        // level, so the AI plays on
+        saved_game("draw_ai_declines", START, settings).await;
+        let chess_state = send(&connection("draw_ai_declines", Seat::White), "OfferDraw").await.unwrap();
+        assert_eq!((chess_state.draw_offer, chess_state.winner), (None, Winner::NoneYet));
+
+        // a king against four queens takes the draw
+        saved_game("draw_ai_accepts", "4k3/8/8/8/8/8/8/QQQQK3 w - - 0 1", settings).await;
+        let chess_state = send(&connection("draw_ai_accepts", Seat::White), "OfferDraw").await.unwrap();
+        assert_eq!(chess_state.winner, Winner::Tie);
+        // and the game was saved that way
+        assert_eq!(Game::load(&"draw_ai_accepts".to_string()).await.get_current_state().unwrap().winner, Winner::Tie);
+    }
+
+    #[tokio::test]
+    async fn test_undo_against_the_ai_takes_back_its_reply() {
+        let settings = Settings { ai_player: Some(Player::Black), ..Settings::new(false, false, false) };
+        saved_game("undo_ai", START, settings).await;
+        let mut game = Game::load(&"undo_ai".to_string()).await;
+        assert!(game.move_piece(1, 4, 3, 4));
+        assert!(game.play_ai_turns());
+        assert!(game.save().await);
+
+        assert_eq!(send(&connection("undo_ai", Seat::Black), "GetPreviousState").await.unwrap_err(), "Your seat can't play for this side");
+        let white = connection("undo_ai", Seat::White);
+        let chess_state = send(&white, "GetPreviousState").await.unwrap();
+        assert_eq!((chess_state.turn_count, chess_state.player), (0, Player::White));
+        // the AI waits for the human's next move rather than replying again
+        let game = Game::load(&"undo_ai".to_string()).await;
+        assert_eq!(game.get_current_state().unwrap().turn_count, 0);
+
+        // nothing of the human's to take back before their first move
+        let settings = Settings { ai_player: Some(Player::White), ..settings };
+        saved_game("undo_ai_first_move", START, settings).await;
+        let mut game = Game::load(&"undo_ai_first_move".to_string()).await;
+        assert!(game.play_ai_turns());
+        assert!(game.save().await);
+        let chess_state = send(&connection("undo_ai_first_move", Seat::Black), "GetPreviousState").await.unwrap();
+        assert_eq!((chess_state.turn_count, chess_state.player), (1, Player::Black));
+    }
+}